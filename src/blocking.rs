@@ -0,0 +1,127 @@
+//! Synchronous CDDBP connection, for applications without an async runtime
+//!
+//! ```no_run
+//! use discid::DiscId;
+//! use gnudb::blocking::Connection;
+//!
+//! let discid = DiscId::read(Some(DiscId::default_device().as_str())).unwrap();
+//! let mut con = Connection::new().unwrap();
+//! let matches = con.query(&discid).unwrap();
+//! let _disc = con.read(&matches[0]).unwrap();
+//! con.close();
+//! ```
+
+use discid::DiscId;
+
+use crate::error::GnuDbError;
+use crate::runtime::Executor;
+use crate::{
+    Category, ClientBuilder, Disc, Match, Motd, ParseOptions, ServerStats, ServerVersion, Site,
+};
+
+/// Blocking version of `gnudb::Connection`, every method waits for the reply of the server
+/// The connection owns a single threaded executor of the runtime that drives the async connection
+pub struct Connection {
+    inner: crate::Connection,
+    executor: Executor,
+}
+
+impl Connection {
+    /// open a connection with the settings of the builder
+    pub fn from_builder(builder: &ClientBuilder) -> Result<Connection, GnuDbError> {
+        let executor = Executor::new()?;
+        let inner = executor.block_on(builder.connect())?;
+        Ok(Connection { inner, executor })
+    }
+
+    /// create a new connection to given host:port combination
+    pub fn from_host_port(host: &str, port: u16) -> Result<Connection, GnuDbError> {
+        Connection::from_builder(&ClientBuilder::new().server(host).cddbp_port(port))
+    }
+
+    /// create a new connection to gnudb.gnudb.org port 8880
+    pub fn new() -> Result<Connection, GnuDbError> {
+        Connection::from_builder(&ClientBuilder::new())
+    }
+
+    /// connect to the first CDDBP site of the list that accepts us, with the settings of the builder
+    /// unreachable or busy servers are skipped, the other sites in the list are ignored
+    pub fn from_sites(builder: &ClientBuilder, sites: &[Site]) -> Result<Connection, GnuDbError> {
+        let executor = Executor::new()?;
+        let inner = executor.block_on(builder.connect_sites(sites))?;
+        Ok(Connection { inner, executor })
+    }
+
+    /// query gnudb for a given discid
+    /// returns a vector of matches or an error
+    pub fn query(&mut self, discid: &DiscId) -> Result<Vec<Match>, GnuDbError> {
+        self.executor.block_on(self.inner.query(discid))
+    }
+
+    /// read all data of a given disc
+    pub fn read(&mut self, single_match: &Match) -> Result<Disc, GnuDbError> {
+        self.executor.block_on(self.inner.read(single_match))
+    }
+
+    /// list the servers of the database, as known by this server
+    pub fn sites(&mut self) -> Result<Vec<Site>, GnuDbError> {
+        self.executor.block_on(self.inner.sites())
+    }
+
+    /// get the message of the day
+    pub fn motd(&mut self) -> Result<Motd, GnuDbError> {
+        self.executor.block_on(self.inner.motd())
+    }
+
+    /// get the server status
+    pub fn stat(&mut self) -> Result<ServerStats, GnuDbError> {
+        self.executor.block_on(self.inner.stat())
+    }
+
+    /// get the server version
+    pub fn ver(&mut self) -> Result<ServerVersion, GnuDbError> {
+        self.executor.block_on(self.inner.ver())
+    }
+
+    /// list the users connected to the server
+    pub fn whom(&mut self) -> Result<Vec<String>, GnuDbError> {
+        self.executor.block_on(self.inner.whom())
+    }
+
+    /// get the help text of the server, for all commands or for a single one
+    pub fn help(&mut self, topic: Option<&str>) -> Result<String, GnuDbError> {
+        self.executor.block_on(self.inner.help(topic))
+    }
+
+    /// list the categories of the database
+    pub fn lscat(&mut self) -> Result<Vec<Category>, GnuDbError> {
+        self.executor.block_on(self.inner.lscat())
+    }
+
+    /// let the server calculate the discid for the given track offsets (in frames)
+    /// and total disc length (in seconds)
+    pub fn discid(&mut self, offsets: &[u32], length_secs: u32) -> Result<String, GnuDbError> {
+        self.executor
+            .block_on(self.inner.discid(offsets, length_secs))
+    }
+
+    /// end the session: the server says goodbye and the connection is closed
+    pub fn quit(&mut self) -> Result<(), GnuDbError> {
+        self.executor.block_on(self.inner.quit())
+    }
+
+    /// set the options used to parse the records returned by `read`
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.inner.set_parse_options(options);
+    }
+
+    pub fn close(&mut self) {
+        self.inner.close();
+    }
+
+    /// protocol level of the session, lower than the desired one when the server did not support it
+    #[must_use]
+    pub fn proto_level(&self) -> u32 {
+        self.inner.proto_level()
+    }
+}
@@ -0,0 +1,680 @@
+//! Persistent cache of query and read results, see `CachedClient`
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use discid::DiscId;
+use log::debug;
+
+use crate::error::GnuDbError;
+use crate::local::{read_record, record_path};
+use crate::transport::{BoxFuture, CddbClient};
+use crate::{
+    Category, Disc, Match, ParseOptions, Site, Submission, SubmitMode, SubmitResponse, parser,
+    runtime,
+};
+
+/// directory of the cached query results, not a valid category name,
+/// so a `LocalDb` on the same directory skips it
+const QUERY_DIR: &str = ".queries";
+const DEFAULT_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Directory where `CachedClient` keeps the records it read as the server sent them, in the freedb layout
+/// (`<root>/<category>/<discid>`, so it can be opened as a `LocalDb` too),
+/// and the results of its queries in `<root>/.queries/<discid>`
+///
+/// Entries older than the time to live are fetched again, but still served when the server
+/// cannot be reached. Queries without matches are not cached, the disc may be submitted
+/// any time. A record is only replaced by one with the same or a higher revision.
+/// When the cache grows beyond its size cap, the oldest entries are removed.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    root: Arc<PathBuf>,
+    ttl: Duration,
+    max_size: Option<u64>,
+    options: ParseOptions,
+    /// bytes taken by the entries, counted on the first store under a size cap and kept up
+    /// to date after that, shared by the clones
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+/// a record as the server sent it, and parsed
+type Record = (String, Disc);
+
+/// a cache entry, and whether it is older than the time to live
+enum Lookup<T> {
+    Fresh(T),
+    Stale(T),
+    Missing,
+}
+
+impl DiskCache {
+    /// cache in the given directory, created when the first entry is stored
+    /// entries live for 30 days, the size is not limited
+    pub fn new(root: impl Into<PathBuf>) -> DiskCache {
+        DiskCache {
+            root: Arc::new(root.into()),
+            ttl: DEFAULT_TTL,
+            max_size: None,
+            options: ParseOptions::default(),
+            size: Arc::default(),
+        }
+    }
+
+    /// how long entries are served without asking the server
+    #[must_use]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// most bytes the cached entries may take, beyond that the oldest are removed until
+    /// the cache is down to 90% of it, so a full cache is not scanned on every store
+    #[must_use]
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// options used to parse the cached records
+    #[must_use]
+    pub fn parse_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// directory of the cache
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// remove the cached record of a disc, so the next read asks the server
+    pub fn invalidate(&self, category: &Category, discid: &str) -> Result<(), GnuDbError> {
+        let path = record_path(&self.root, category, discid)?;
+        let mut size = self.size();
+        let len = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            result => {
+                if result.is_ok()
+                    && let Some(size) = size.as_mut()
+                {
+                    *size = size.saturating_sub(len);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn size(&self) -> MutexGuard<'_, Option<u64>> {
+        self.size.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_fresh(&self, path: &Path) -> io::Result<bool> {
+        let age = fs::metadata(path)?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default();
+        Ok(age < self.ttl)
+    }
+
+    fn lookup<T>(
+        &self,
+        path: &Path,
+        parse: impl FnOnce(&str) -> Result<Option<T>, GnuDbError>,
+    ) -> Lookup<T> {
+        let entry = read_record(path)
+            .map_err(GnuDbError::from)
+            .and_then(|data| parse(&data))
+            .and_then(|entry| Ok((entry, self.is_fresh(path)?)));
+        match entry {
+            Ok((Some(entry), true)) => Lookup::Fresh(entry),
+            Ok((Some(entry), false)) => Lookup::Stale(entry),
+            Ok((None, _)) => Lookup::Missing,
+            Err(GnuDbError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Lookup::Missing,
+            Err(e) => {
+                debug!("ignoring cache entry {}: {e}", path.display());
+                Lookup::Missing
+            }
+        }
+    }
+
+    fn parse_record(&self, raw: String) -> Result<Record, GnuDbError> {
+        let disc = parser::parse_read_response_with(&raw, &self.options)?;
+        Ok((raw, disc))
+    }
+
+    fn lookup_record(&self, single_match: &Match) -> Lookup<Record> {
+        let Ok(path) = record_path(&self.root, &single_match.category, &single_match.discid) else {
+            return Lookup::Missing;
+        };
+        self.lookup(&path, |data| self.parse_record(data.to_owned()).map(Some))
+    }
+
+    /// store a record unless the cache has a higher revision, returns the record that is kept
+    /// the record is written as it was received, comments and all
+    fn store_record(
+        &self,
+        category: &Category,
+        discid: &str,
+        raw: String,
+    ) -> Result<Record, GnuDbError> {
+        let path = record_path(&self.root, category, discid)?;
+        let (raw, disc) = self.parse_record(raw)?;
+        let kept = match read_record(&path)
+            .ok()
+            .and_then(|data| self.parse_record(data).ok())
+        {
+            Some((data, cached)) if cached.revision > disc.revision => {
+                debug!(
+                    "keeping revision {} of {category}/{discid} over {}",
+                    cached.revision, disc.revision
+                );
+                (data, cached)
+            }
+            _ => (raw, disc),
+        };
+        fs::create_dir_all(self.root.join(category.as_str()))?;
+        // written again in any case, so the kept record is fresh
+        self.write_entry(&path, kept.0.as_bytes())?;
+        Ok(kept)
+    }
+
+    fn query_path(&self, discid: &str) -> Result<PathBuf, GnuDbError> {
+        // checks the discid like for a record
+        record_path(&self.root, &Category::Misc, discid)?;
+        Ok(self.root.join(QUERY_DIR).join(discid))
+    }
+
+    /// the matches of a query, the first line of the entry is the query command,
+    /// so discs with the same discid but another TOC are not mixed up
+    fn lookup_query(&self, cmd: &str, discid: &str) -> Lookup<Vec<Match>> {
+        let Ok(path) = self.query_path(discid) else {
+            return Lookup::Missing;
+        };
+        self.lookup(&path, |data| {
+            let mut lines = data.lines();
+            if lines.next() != Some(format!("# {}", cmd.trim_end()).as_str()) {
+                return Ok(None);
+            }
+            lines
+                .map(parser::parse_matches)
+                .collect::<Result<_, _>>()
+                .map(Some)
+        })
+    }
+
+    fn store_query(&self, cmd: &str, discid: &str, matches: &[Match]) -> Result<(), GnuDbError> {
+        let path = self.query_path(discid)?;
+        let mut entry = format!("# {}\n", cmd.trim_end());
+        for m in matches {
+            entry.push_str(&format!(
+                "{} {} {} / {}\n",
+                m.category, m.discid, m.artist, m.title
+            ));
+        }
+        fs::create_dir_all(self.root.join(QUERY_DIR))?;
+        self.write_entry(&path, entry.as_bytes())?;
+        Ok(())
+    }
+
+    /// write an entry and update the size of the cache, removing the oldest entries when it
+    /// grew beyond its size cap
+    /// the lock of the size is held across the write, so concurrent stores of an entry count it once
+    fn write_entry(&self, path: &Path, data: &[u8]) -> Result<(), GnuDbError> {
+        let mut size = self.size();
+        let replaced = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        write_atomic(path, data)?;
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+        let current = match *size {
+            Some(size) => {
+                size.saturating_sub(replaced) + u64::try_from(data.len()).unwrap_or(u64::MAX)
+            }
+            None => self.entries().iter().map(|(_, len, _)| len).sum(),
+        };
+        *size = Some(if current > max_size {
+            self.evict(max_size / 10 * 9)
+        } else {
+            current
+        });
+        Ok(())
+    }
+
+    /// the modification time, size and path of every entry
+    fn entries(&self) -> Vec<(SystemTime, u64, PathBuf)> {
+        fs::read_dir(self.root.as_path())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|dir| dir.file_type().is_ok_and(|kind| kind.is_dir()))
+            .filter_map(|dir| fs::read_dir(dir.path()).ok())
+            .flatten()
+            .flatten()
+            .filter_map(|file| {
+                let metadata = file.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), file.path()))
+            })
+            .collect()
+    }
+
+    /// remove the oldest entries until the cache takes at most `target` bytes, returns its size
+    /// the entries are counted again, so changes by other processes are picked up
+    fn evict(&self, target: u64) -> u64 {
+        let mut entries = self.entries();
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if size <= target {
+                break;
+            }
+            debug!("cache full, removing {}", path.display());
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+        size
+    }
+}
+
+/// write a temporary file next to the entry and move it over the entry, so a lookup never
+/// reads a half written entry; the name starts with a dot, so it is never taken for an entry
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(
+        ".{name}.{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = fs::write(&temp, data).and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Cache in front of another `CddbClient`: query and read results are stored in a `DiskCache`
+/// and served from it on the next lookup, also when the server cannot be reached
+/// ```no_run
+/// use std::time::Duration;
+/// use discid::DiscId;
+/// use gnudb::{CachedClient, CddbClient, ClientBuilder, DiskCache};
+///
+/// #[cfg(feature = "runtime-smol")]
+/// smol::block_on(async {
+///     let cache = DiskCache::new("/var/cache/gnudb")
+///         .ttl(Duration::from_secs(7 * 24 * 60 * 60))
+///         .max_size(100 * 1024 * 1024);
+///     let mut client = CachedClient::new(ClientBuilder::new().http_async().unwrap(), cache);
+///     let discid = DiscId::read(Some(DiscId::default_device().as_str())).unwrap();
+///     let matches = client.query(&discid).await.unwrap();
+///     let _disc = client.read(&matches[0]).await.unwrap();
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct CachedClient<C> {
+    inner: C,
+    cache: DiskCache,
+}
+
+impl<C: CddbClient + Send> CachedClient<C> {
+    pub fn new(inner: C, cache: DiskCache) -> CachedClient<C> {
+        CachedClient { inner, cache }
+    }
+
+    /// the client the lookups that are not cached go to
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    #[must_use]
+    pub fn cache(&self) -> &DiskCache {
+        &self.cache
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// the record of a disc from the cache, or from the inner client when it is missing or stale
+    async fn record(&mut self, single_match: &Match) -> Result<Record, GnuDbError> {
+        let lookup = {
+            let (cache, single_match) = (self.cache.clone(), single_match.clone());
+            runtime::unblock(move || cache.lookup_record(&single_match)).await
+        };
+        let stale = match lookup {
+            Lookup::Fresh(record) => {
+                debug!(
+                    "{}/{} served from the cache",
+                    single_match.category, single_match.discid
+                );
+                return Ok(record);
+            }
+            Lookup::Stale(record) => Some(record),
+            Lookup::Missing => None,
+        };
+        let raw = match self.inner.read_raw(single_match).await {
+            Ok(raw) => raw,
+            Err(e) => return stale_or(stale, e),
+        };
+        let cache = self.cache.clone();
+        let single_match = single_match.clone();
+        runtime::unblock(move || {
+            let category = &single_match.category;
+            let discid = &single_match.discid;
+            cache
+                .store_record(category, discid, raw.clone())
+                .or_else(|e| {
+                    debug!("failed to cache {category}/{discid}: {e}");
+                    cache.parse_record(raw)
+                })
+        })
+        .await
+    }
+}
+
+/// serve a stale entry when the server cannot be reached, like a server that went offline
+fn stale_or<T>(stale: Option<T>, err: GnuDbError) -> Result<T, GnuDbError> {
+    match stale {
+        Some(entry) if err.is_server_unavailable() => {
+            debug!("serving a stale cache entry: {err}");
+            Ok(entry)
+        }
+        _ => Err(err),
+    }
+}
+
+impl<C: CddbClient + Send> CddbClient for CachedClient<C> {
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+        let cmd = parser::create_query_cmd(discid);
+        let id = discid.freedb_id();
+        // the futures do not borrow the DiscId, which is not Send
+        let fetch = self.inner.query(discid);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let cmd = cmd?;
+            let lookup = {
+                let (cache, cmd, id) = (cache.clone(), cmd.clone(), id.clone());
+                runtime::unblock(move || cache.lookup_query(&cmd, &id)).await
+            };
+            let stale = match lookup {
+                Lookup::Fresh(matches) => {
+                    debug!("query {id} served from the cache");
+                    return Ok(matches);
+                }
+                Lookup::Stale(matches) => Some(matches),
+                Lookup::Missing => None,
+            };
+            let matches = match fetch.await {
+                Ok(matches) => matches,
+                Err(e) => return stale_or(stale, e),
+            };
+            if matches.is_empty() {
+                return Ok(matches);
+            }
+            let stored = matches.clone();
+            runtime::unblock(move || {
+                if let Err(e) = cache.store_query(&cmd, &id, &stored) {
+                    debug!("failed to cache query {id}: {e}");
+                }
+            })
+            .await;
+            Ok(matches)
+        })
+    }
+
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+        Box::pin(async move { self.record(single_match).await.map(|(_, disc)| disc) })
+    }
+
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        Box::pin(async move { self.record(single_match).await.map(|(raw, _)| raw) })
+    }
+
+    fn sites(&mut self) -> BoxFuture<'_, Result<Vec<Site>, GnuDbError>> {
+        self.inner.sites()
+    }
+
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        self.inner.lscat()
+    }
+
+    /// submit through the inner client, an accepted submission replaces the cached records
+    /// of the disc, since it has a higher revision
+    fn submit<'a>(
+        &'a mut self,
+        submission: &'a Submission<'a>,
+    ) -> BoxFuture<'a, Result<SubmitResponse, GnuDbError>> {
+        let cache = self.cache.clone();
+        // the records to store are built before the future, since the DiscId is not Send
+        let submitted = (submission.mode == SubmitMode::Submit).then(|| {
+            let mut disc = submission.disc.clone();
+            disc.set_toc(submission.discid);
+            (submission.category.clone(), disc)
+        });
+        let response = self.inner.submit(submission);
+        Box::pin(async move {
+            let response = response.await?;
+            if let Some((category, disc)) = submitted {
+                runtime::unblock(move || {
+                    let record = disc.to_xmcd();
+                    for discid in &disc.discids {
+                        if let Err(e) = cache.store_record(&category, discid, record.clone()) {
+                            debug!("failed to cache {category}/{discid}: {e}");
+                        }
+                    }
+                })
+                .await;
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{sample_disc, sample_match, temp_root};
+    use crate::{LocalDb, runtime::Executor};
+
+    /// stand-in for a server, counting the lookups that reach it
+    struct Server {
+        online: bool,
+        matches: Vec<Match>,
+        revision: u32,
+        lookups: usize,
+    }
+
+    impl Server {
+        fn new() -> Server {
+            Server {
+                online: true,
+                matches: vec![sample_match()],
+                revision: 1,
+                lookups: 0,
+            }
+        }
+
+        fn answer<T>(&mut self, answer: T) -> BoxFuture<'_, Result<T, GnuDbError>>
+        where
+            T: Send + 'static,
+        {
+            // counted when the lookup runs, like the real clients the futures are lazy
+            Box::pin(async move {
+                self.lookups += 1;
+                if self.online {
+                    Ok(answer)
+                } else {
+                    Err(GnuDbError::ConnectionError("offline".to_owned()))
+                }
+            })
+        }
+    }
+
+    impl CddbClient for Server {
+        fn query(&mut self, _discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+            self.answer(self.matches.clone())
+        }
+
+        fn read<'a>(&'a mut self, _m: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+            let disc = Disc {
+                revision: self.revision,
+                discids: vec!["6909aa09".to_owned()],
+                ..sample_disc()
+            };
+            self.answer(disc)
+        }
+
+        /// the record with a comment that only survives when it is stored as received
+        fn read_raw<'a>(&'a mut self, _m: &'a Match) -> BoxFuture<'a, Result<String, GnuDbError>> {
+            let disc = Disc {
+                revision: self.revision,
+                discids: vec!["6909aa09".to_owned()],
+                ..sample_disc()
+            };
+            let raw = disc
+                .to_xmcd()
+                .replacen("# xmcd\n", "# xmcd\n# Ripped from vinyl\n", 1);
+            self.answer(raw)
+        }
+    }
+
+    #[test]
+    fn test_read_and_query_cached() -> Result<(), GnuDbError> {
+        let executor = Executor::new()?;
+        let root = temp_root("hit");
+        let mut client = CachedClient::new(Server::new(), DiskCache::new(&root));
+        let disc = executor.block_on(client.read(&sample_match()))?;
+        let cached = executor.block_on(client.read(&sample_match()))?;
+        assert_eq!(client.inner_mut().lookups, 1);
+        assert_eq!(cached.title, disc.title);
+        assert_eq!(cached.revision, disc.revision);
+        assert_eq!(cached.tracks[0].title, "Down to the waterline");
+        // the record is stored as the server sent it
+        let raw = executor.block_on(client.read_raw(&sample_match()))?;
+        assert!(raw.contains("# Ripped from vinyl\n"));
+        assert_eq!(fs::read_to_string(root.join("rock").join("6909aa09"))?, raw);
+        // without leftovers of the write
+        assert_eq!(fs::read_dir(root.join("rock"))?.count(), 1);
+        assert_eq!(client.inner_mut().lookups, 1);
+        // the records are in the freedb layout
+        let mut local = LocalDb::new(&root);
+        assert_eq!(executor.block_on(local.read(&sample_match()))?, cached);
+
+        let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+        let matches = executor.block_on(client.query(&discid))?;
+        let cached = executor.block_on(client.query(&discid))?;
+        assert_eq!(client.inner_mut().lookups, 2);
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].category, matches[0].category);
+        assert_eq!(cached[0].title, "Dire Straits");
+        // another TOC with the same discid is not served from the cache
+        assert!(matches!(
+            client
+                .cache()
+                .lookup_query("cddb query other", &discid.freedb_id()),
+            Lookup::Missing
+        ));
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_disc_not_cached() -> Result<(), GnuDbError> {
+        let executor = Executor::new()?;
+        let root = temp_root("unknown");
+        let mut server = Server::new();
+        server.matches.clear();
+        let mut client = CachedClient::new(server, DiskCache::new(&root));
+        let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+        assert!(executor.block_on(client.query(&discid))?.is_empty());
+        // the disc was submitted in the meantime
+        client.inner_mut().matches = vec![sample_match()];
+        assert_eq!(executor.block_on(client.query(&discid))?.len(), 1);
+        assert_eq!(client.inner_mut().lookups, 2);
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_entries() -> Result<(), GnuDbError> {
+        let executor = Executor::new()?;
+        let root = temp_root("stale");
+        let mut client =
+            CachedClient::new(Server::new(), DiskCache::new(&root).ttl(Duration::ZERO));
+        executor.block_on(client.read(&sample_match()))?;
+        executor.block_on(client.read(&sample_match()))?;
+        assert_eq!(client.inner_mut().lookups, 2);
+
+        // served offline
+        client.inner_mut().online = false;
+        assert_eq!(executor.block_on(client.read(&sample_match()))?.revision, 1);
+        let missing = Match {
+            category: Category::Jazz,
+            ..sample_match()
+        };
+        assert!(executor.block_on(client.read(&missing)).is_err());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_revision_and_invalidate() -> Result<(), GnuDbError> {
+        let executor = Executor::new()?;
+        let root = temp_root("revision");
+        let mut server = Server::new();
+        server.revision = 5;
+        let mut client = CachedClient::new(server, DiskCache::new(&root).ttl(Duration::ZERO));
+        executor.block_on(client.read(&sample_match()))?;
+        // a mirror that is behind does not replace the newer record
+        client.inner_mut().revision = 3;
+        assert_eq!(executor.block_on(client.read(&sample_match()))?.revision, 5);
+        client.inner_mut().revision = 6;
+        assert_eq!(executor.block_on(client.read(&sample_match()))?.revision, 6);
+
+        client.cache().invalidate(&Category::Rock, "6909aa09")?;
+        assert!(!root.join("rock").join("6909aa09").exists());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_size() -> Result<(), GnuDbError> {
+        let root = temp_root("size");
+        let cache = DiskCache::new(&root);
+        let record = Disc {
+            title: "Title".to_owned(),
+            ..Default::default()
+        }
+        .to_xmcd();
+        cache.store_record(&Category::Rock, "00000001", record.clone())?;
+        let size = fs::metadata(root.join("rock").join("00000001"))?.len();
+        std::thread::sleep(Duration::from_millis(20));
+        // room for two and a half records, a third makes room down to 90% of that
+        let cache = cache.max_size(size * 5 / 2);
+        cache.store_record(&Category::Jazz, "00000002", record.clone())?;
+        std::thread::sleep(Duration::from_millis(20));
+        cache.store_record(&Category::Jazz, "00000003", record)?;
+        assert!(!root.join("rock").join("00000001").exists());
+        assert!(root.join("jazz").join("00000002").exists());
+        assert!(root.join("jazz").join("00000003").exists());
+        assert_eq!(*cache.size(), Some(size * 2));
+        cache.invalidate(&Category::Jazz, "00000002")?;
+        assert_eq!(*cache.size(), Some(size));
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
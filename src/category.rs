@@ -0,0 +1,151 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::GnuDbError;
+
+/// A CDDB category
+/// Servers may define categories outside of the eleven standard ones, these are kept as `Unknown`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Category {
+    Blues,
+    Classical,
+    Country,
+    Data,
+    Folk,
+    Jazz,
+    Misc,
+    Newage,
+    Reggae,
+    Rock,
+    Soundtrack,
+    Unknown(String),
+}
+
+impl Category {
+    /// the eleven standard CDDB categories
+    pub const ALL: [Category; 11] = [
+        Category::Blues,
+        Category::Classical,
+        Category::Country,
+        Category::Data,
+        Category::Folk,
+        Category::Jazz,
+        Category::Misc,
+        Category::Newage,
+        Category::Reggae,
+        Category::Rock,
+        Category::Soundtrack,
+    ];
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Category::Blues => "blues",
+            Category::Classical => "classical",
+            Category::Country => "country",
+            Category::Data => "data",
+            Category::Folk => "folk",
+            Category::Jazz => "jazz",
+            Category::Misc => "misc",
+            Category::Newage => "newage",
+            Category::Reggae => "reggae",
+            Category::Rock => "rock",
+            Category::Soundtrack => "soundtrack",
+            Category::Unknown(name) => name,
+        }
+    }
+
+    /// true for the eleven standard categories
+    #[must_use]
+    pub fn is_standard(&self) -> bool {
+        !matches!(self, Category::Unknown(_))
+    }
+
+    /// parse a category as sent by a server: unknown names are kept as `Unknown`
+    pub(crate) fn from_server(name: &str) -> Category {
+        name.parse()
+            .unwrap_or_else(|_| Category::Unknown(name.to_owned()))
+    }
+
+    /// check that the category can be sent in a command:
+    /// an `Unknown` category must be a single lowercase word
+    pub(crate) fn validate(&self) -> Result<(), GnuDbError> {
+        let name = self.as_str();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+        if valid {
+            Ok(())
+        } else {
+            Err(GnuDbError::InvalidCategory(name.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// parse one of the eleven standard categories (case insensitive)
+impl FromStr for Category {
+    type Err = GnuDbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| GnuDbError::InvalidCategory(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_standard_categories() -> Result<(), GnuDbError> {
+        for category in Category::ALL {
+            assert_eq!(category.as_str().parse::<Category>()?, category);
+            assert_eq!(category.to_string(), category.as_str());
+            assert!(category.is_standard());
+        }
+        assert_eq!("Rock".parse::<Category>()?, Category::Rock);
+        assert_eq!(" newage ".parse::<Category>()?, Category::Newage);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_category() {
+        let err = "pop".parse::<Category>().unwrap_err();
+        assert!(matches!(err, GnuDbError::InvalidCategory(name) if name == "pop"));
+    }
+
+    #[test]
+    fn test_from_server() {
+        assert_eq!(Category::from_server("jazz"), Category::Jazz);
+        let unknown = Category::from_server("electronic");
+        assert_eq!(unknown, Category::Unknown("electronic".to_owned()));
+        assert!(!unknown.is_standard());
+        assert_eq!(unknown.to_string(), "electronic");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Category::Rock.validate().is_ok());
+        assert!(
+            Category::Unknown("electronic".to_owned())
+                .validate()
+                .is_ok()
+        );
+        assert!(Category::Unknown(String::new()).validate().is_err());
+        assert!(Category::Unknown("rock 123".to_owned()).validate().is_err());
+        assert!(
+            Category::Unknown("rock\ncddb quit".to_owned())
+                .validate()
+                .is_err()
+        );
+        assert!(Category::Unknown("Rock".to_owned()).validate().is_err());
+    }
+}
@@ -1,46 +1,153 @@
+use futures_lite::{
+    FutureExt,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+};
 use log::debug;
-use smol::{Timer, io::BufReader, net::TcpStream, prelude::*};
 
-use crate::error::GnuDbError;
+use crate::error::{Command, GnuDbError};
 use crate::parser::{
-    create_read_cmd, parse_query_response, parse_raw_response, parse_read_response,
+    create_proto_cmd, create_read_cmd, parse_proto_response, parse_query_response,
+    parse_raw_response, parse_read_response_with,
 };
-use crate::{Connection, Disc, HELLO_STRING, Match, TIMEOUT};
-
-const PROTO_CMD: &str = "proto 6\n";
-
-/// connect the tcp stream, login and set the protocol to 6
-pub(crate) async fn connect(s: String) -> Result<Connection, GnuDbError> {
-    let stream = TcpStream::connect(&s)
-        .or(async {
-            Timer::after(TIMEOUT).await;
-            Err(std::io::Error::new(
-                std::io::ErrorKind::TimedOut,
-                "connection timed out",
-            ))
-        })
+use crate::runtime::{self, Stream, timeout};
+use crate::{ClientBuilder, Connection, Disc, Match};
+use crate::{retry, server};
+
+/// connect the tcp stream, login and set the protocol level, on the server of the builder
+/// or on the first CDDBP site of its mirror list that accepts us
+/// busy servers and failed connections are retried with the retry policy of the builder
+pub(crate) async fn connect(builder: &ClientBuilder) -> Result<Connection, GnuDbError> {
+    builder.config.validate()?;
+    let mut attempt = 1;
+    loop {
+        match connect_any(builder).await {
+            Ok(con) => return Ok(con),
+            Err(e) if builder.retry.should_retry(attempt, &e) => {
+                let backoff = builder.retry.backoff(attempt);
+                debug!("connection attempt {attempt} failed, retrying in {backoff:?}: {e}");
+                runtime::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// connect to the server of the builder, or fail over across the CDDBP sites of its mirror list,
+/// starting after the current server so that a reconnect moves on to the next site
+async fn connect_any(builder: &ClientBuilder) -> Result<Connection, GnuDbError> {
+    if mirror_count(builder) == 0 {
+        return connect_once(builder).await;
+    }
+    let first = server::position(&builder.sites, "cddbp", &builder.server, builder.cddbp_port)
+        .map_or(0, |current| current + 1);
+    let sites = server::rotated(&builder.sites, first);
+    let mut fail_over = server::FailOver::new("cddbp");
+    for site in fail_over.sites(&sites) {
+        match connect_once(&builder.clone().site(site)).await {
+            Ok(con) => return Ok(con),
+            Err(e) => fail_over.skip(site, e)?,
+        }
+    }
+    Err(fail_over.into_error())
+}
+
+/// connect to the server of the builder, counted by its rate limiter
+async fn connect_once(builder: &ClientBuilder) -> Result<Connection, GnuDbError> {
+    let permit = match &builder.rate_limiter {
+        Some(limiter) => Some(
+            limiter
+                .acquire_connection(&builder.server, builder.connect_timeout)
+                .await?,
+        ),
+        None => None,
+    };
+    let s = format!("{}:{}", builder.server, builder.cddbp_port);
+    let stream = runtime::connect_tcp(&s)
+        .or(timeout(builder.connect_timeout, "connection timed out"))
         .await?;
-    let mut reader = BufReader::new(stream);
     debug!("Successfully connected to server {}", &s);
+    let mut con = handshake(stream, builder).await?;
+    if builder.retry.max_attempt_count() > 1 || mirror_count(builder) > 0 {
+        con.reconnect = Some(Box::new(builder.clone()));
+    }
+    con.permit = permit;
+    Ok(con)
+}
+
+/// number of CDDBP sites in the mirror list of the builder
+fn mirror_count(builder: &ClientBuilder) -> usize {
+    builder
+        .sites
+        .iter()
+        .filter(|site| site.protocol == "cddbp")
+        .count()
+}
+
+/// login and set the protocol level over an established stream
+pub(crate) async fn handshake(
+    stream: Box<dyn Stream>,
+    builder: &ClientBuilder,
+) -> Result<Connection, GnuDbError> {
+    let hello = builder.config.hello()?;
+    let mut con = Connection {
+        reader: Some(BufReader::new(stream)),
+        options: builder.options.clone(),
+        read_timeout: builder.read_timeout,
+        proto_level: 1,
+        retry: builder.retry,
+        reconnect: None,
+        limiter: builder.rate_limiter.clone(),
+        permit: None,
+    };
     // say hello -> this is the login
     let mut server_hello = String::new();
-    read_line_with_timeout(&mut reader, &mut server_hello).await?;
-    let our_hello = format!("cddb hello {HELLO_STRING}\n");
-    send_command(&mut reader, our_hello).await?;
+    read_line_with_timeout(&mut con, &mut server_hello).await?;
+    // 200/201 means we may go on, 432/433/434 mean the server does not accept us right now
+    if !server_hello.starts_with('2') {
+        return Err(GnuDbError::from_status(&server_hello, Command::Handshake));
+    }
+    let our_hello = format!("cddb hello {hello}\n");
+    parse_raw_response(
+        &read_response(&mut con, &our_hello).await?,
+        Command::Handshake,
+    )?;
+
+    con.proto_level = set_proto(&mut con, builder.proto_level).await?;
+    Ok(con)
+}
 
-    // switch to protocol level 6, so the output of GNUDB contains DYEAR and DGENRE
-    send_command(&mut reader, PROTO_CMD.to_owned()).await?;
-    Ok(Connection::from_reader(reader))
+/// switch to the desired protocol level, or to the highest level the server supports
+/// when it rejects the desired one
+async fn set_proto(con: &mut Connection, level: u32) -> Result<u32, GnuDbError> {
+    let raw = read_response(con, &create_proto_cmd(Some(level))).await?;
+    // 502: already at this level, 501: illegal level
+    if raw.starts_with("502") {
+        return Ok(level);
+    }
+    if !raw.starts_with("501") {
+        parse_raw_response(&raw, Command::Handshake)?;
+        return Ok(level);
+    }
+    let proto = read_response(con, &create_proto_cmd(None)).await?;
+    let supported = parse_proto_response(&proto)?;
+    if supported >= level {
+        return Err(GnuDbError::from_status(&raw, Command::Handshake));
+    }
+    debug!("protocol level {level} rejected, falling back to {supported}");
+    let raw = read_response(con, &create_proto_cmd(Some(supported))).await?;
+    parse_raw_response(&raw, Command::Handshake)?;
+    Ok(supported)
 }
 
 /// specific command to query the disc, first issues a query, and then a read
 /// query protocol: cddb query discid ntrks off1 off2 ... nsecs
 /// if nothing found, will return empty matches
 pub(crate) async fn cddb_query(
-    reader: &mut BufReader<TcpStream>,
+    con: &mut Connection,
     cmd: String,
 ) -> Result<Vec<Match>, GnuDbError> {
-    let response = send_command(reader, cmd).await?;
+    let response = send_command(con, cmd).await?;
     let matches = parse_query_response(&response)?;
     Ok(matches)
 }
@@ -48,16 +155,117 @@ pub(crate) async fn cddb_query(
 /// specific command to read the disc
 /// read protocol: cddb read category discid
 pub(crate) async fn cddb_read(
-    reader: &mut BufReader<TcpStream>,
+    con: &mut Connection,
     single_match: &Match,
 ) -> Result<Disc, GnuDbError> {
-    let cmd = create_read_cmd(single_match);
-    let data = send_command(reader, cmd).await?;
-    let disc = parse_read_response(&data)?;
+    let data = cddb_read_raw(con, single_match).await?;
+    let disc = parse_read_response_with(&data, &con.options)?;
     debug!("disc:{disc:?}");
     Ok(disc)
 }
 
+/// read the xmcd record of the disc as the server sent it, without the status line
+pub(crate) async fn cddb_read_raw(
+    con: &mut Connection,
+    single_match: &Match,
+) -> Result<String, GnuDbError> {
+    let cmd = create_read_cmd(single_match)?;
+    send_command(con, cmd).await
+}
+
+/// send any CDDBP command and return the raw response, status line included
+pub(crate) async fn cddb_command(con: &mut Connection, cmd: &str) -> Result<String, GnuDbError> {
+    exchange(con, cmd).await
+}
+
+/// end the session, never retried
+pub(crate) async fn cddb_quit(con: &mut Connection) -> Result<String, GnuDbError> {
+    read_response(con, "quit\n").await
+}
+
+/// send a command and read its raw response, retrying transient failures with the retry policy
+/// of the connection: a connection that broke is opened again before the command is replayed
+/// a connection with a mirror list replays it on the next site when the server is unreachable
+/// or busy, once per site
+async fn exchange(con: &mut Connection, cmd: &str) -> Result<String, GnuDbError> {
+    let mut attempt = 1;
+    let mut fail_overs = 0;
+    loop {
+        match exchange_once(con, cmd).await {
+            Err(e)
+                if e.is_server_unavailable()
+                    && fail_overs < con.reconnect.as_deref().map_or(0, mirror_count) =>
+            {
+                debug!("'{}' failed, failing over: {e}", cmd.trim_end());
+                // the command is replayed over a new connection to the next site
+                con.reader = None;
+                fail_overs += 1;
+            }
+            Err(e)
+                if con.retry.should_retry(attempt, &e)
+                    && (con.reader.is_some() || con.reconnect.is_some()) =>
+            {
+                let backoff = con.retry.backoff(attempt);
+                debug!(
+                    "attempt {attempt} of '{}' failed, retrying in {backoff:?}: {e}",
+                    cmd.trim_end()
+                );
+                runtime::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn exchange_once(con: &mut Connection, cmd: &str) -> Result<String, GnuDbError> {
+    if con.reader.is_none()
+        && let Some(builder) = con.reconnect.clone()
+    {
+        debug!("reconnecting to {}:{}", builder.server, builder.cddbp_port);
+        // the old connection is not counted anymore, it may be to the same host
+        con.permit = None;
+        let mut fresh = connect_any(&builder).await?;
+        con.reader = fresh.reader.take();
+        con.proto_level = fresh.proto_level;
+        con.reconnect = fresh.reconnect.take();
+        con.permit = fresh.permit.take();
+    }
+    let raw = match read_response(con, cmd).await {
+        Ok(raw) => raw,
+        Err(e) => {
+            if breaks_connection(&e) {
+                // the reply may be half read, so the stream cannot be used anymore
+                con.reader = None;
+            }
+            return Err(e);
+        }
+    };
+    // x3x: the server closes the connection after this reply, like 530 on a server error
+    if raw.chars().nth(1) == Some('3') {
+        debug!("the server closed the connection");
+        con.reader = None;
+    }
+    match retry::failed_reply(&raw, Command::of(cmd)) {
+        Some(e) if con.retry.is_retryable(&e) => Err(e),
+        Some(e)
+            if e.is_server_unavailable()
+                && con.reconnect.as_deref().map_or(0, mirror_count) > 0 =>
+        {
+            Err(e)
+        }
+        _ => Ok(raw),
+    }
+}
+
+/// true for errors after which the connection cannot be used anymore: I/O errors and timeouts
+fn breaks_connection(err: &GnuDbError) -> bool {
+    matches!(
+        err,
+        GnuDbError::ConnectionError(_) | GnuDbError::Io(_) | GnuDbError::Timeout(_)
+    )
+}
+
 /// send a CDDBP command, and parse its output, according to the protocol specs:
 ///
 /// Server response code (three digit code):
@@ -77,32 +285,48 @@ pub(crate) async fn cddb_read(
 ///
 /// Third digit:
 /// xx[0-9]    Command-specific code
-async fn send_command(
-    reader: &mut BufReader<TcpStream>,
-    cmd: String,
-) -> Result<String, GnuDbError> {
-    let raw = read_response(reader, &cmd).await?;
-    parse_raw_response(&raw)
+async fn send_command(con: &mut Connection, cmd: String) -> Result<String, GnuDbError> {
+    let raw = exchange(con, &cmd).await?;
+    parse_raw_response(&raw, Command::of(&cmd))
 }
 
-async fn read_response(reader: &mut BufReader<TcpStream>, cmd: &str) -> Result<String, GnuDbError> {
-    reader.get_mut().write_all(cmd.as_bytes()).await?;
+/// send a command and read its raw response, every command counts as a request for the rate limiter,
+/// those of the login and quit included
+async fn read_response(con: &mut Connection, cmd: &str) -> Result<String, GnuDbError> {
+    if let Some(limiter) = &con.limiter {
+        let wait = limiter.reserve_request();
+        if !wait.is_zero() {
+            debug!("rate limited, waiting {wait:?}");
+            runtime::sleep(wait).await;
+        }
+    }
+    con.reader()?.get_mut().write_all(cmd.as_bytes()).await?;
     debug!("sent {cmd}");
     let mut status = String::new();
-    read_line_with_timeout(reader, &mut status).await?;
+    if read_line_with_timeout(con, &mut status).await? == 0 {
+        return Err(GnuDbError::ConnectionError(
+            "connection closed by the server".to_owned(),
+        ));
+    }
     debug!("response: {status}");
 
-    let second_digit = status.chars().nth(1).ok_or(GnuDbError::ProtocolError(
-        "failed to parse response code".to_string(),
-    ))?;
+    let second_digit = status
+        .chars()
+        .nth(1)
+        .ok_or_else(|| GnuDbError::malformed(status.trim_end(), "failed to parse response code"))?;
     let mut raw = status.clone();
 
     if second_digit == '1' || second_digit == '2' {
         loop {
             let mut line = String::new();
-            let result = read_line_with_timeout(reader, &mut line).await;
+            let result = read_line_with_timeout(con, &mut line).await;
             debug!("response: {line}");
             match result {
+                Ok(0) => {
+                    return Err(GnuDbError::ConnectionError(
+                        "connection closed before the end of the response".to_owned(),
+                    ));
+                }
                 Ok(_) => {
                     if line.trim_end_matches(['\r', '\n']).eq(".") {
                         break;
@@ -111,9 +335,7 @@ async fn read_response(reader: &mut BufReader<TcpStream>, cmd: &str) -> Result<S
                 }
                 Err(e) => {
                     debug!("Failed to receive data: {e}");
-                    return Err(GnuDbError::ProtocolError(format!(
-                        "failed to read line: {e}"
-                    )));
+                    return Err(e);
                 }
             }
         }
@@ -123,18 +345,117 @@ async fn read_response(reader: &mut BufReader<TcpStream>, cmd: &str) -> Result<S
 }
 
 async fn read_line_with_timeout(
-    reader: &mut BufReader<TcpStream>,
+    con: &mut Connection,
     buf: &mut String,
 ) -> Result<usize, GnuDbError> {
-    let read = reader.read_line(buf);
-    let timeout = async {
-        Timer::after(TIMEOUT).await;
-        Err(std::io::Error::new(
-            std::io::ErrorKind::TimedOut,
-            "read timed out",
-        ))
-    };
-    smol::future::or(read, timeout)
+    let read_timeout = con.read_timeout;
+    con.reader()?
+        .read_line(buf)
+        .or(timeout(read_timeout, "read timed out"))
         .await
         .map_err(GnuDbError::from)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test::{READY, Script, ScriptedStream};
+    use crate::{Category, runtime::Executor};
+
+    /// a connection after the handshake, and the commands it sent
+    fn scripted(script: Script) -> (Connection, Arc<Mutex<Vec<String>>>) {
+        let (stream, commands) = ScriptedStream::new(READY, script);
+        let con = Executor::new()
+            .unwrap()
+            .block_on(Connection::from_stream(stream, &ClientBuilder::new()))
+            .unwrap();
+        (con, commands)
+    }
+
+    #[test]
+    fn test_handshake_over_stream() {
+        let (con, commands) = scripted(&[]);
+        assert_eq!(con.proto_level(), 6);
+        let commands = commands.lock().unwrap();
+        assert!(commands[0].starts_with("cddb hello anonymous localhost gnudb "));
+        assert_eq!(commands[1..], ["proto 6"]);
+    }
+
+    #[test]
+    fn test_read_response_multi_line() -> Result<(), GnuDbError> {
+        let (mut con, _) = scripted(&[
+            (
+                "cddb lscat",
+                "210 OK, category list follows (until terminating `.')\r\nrock\r\njazz\r\n.\r\n",
+            ),
+            (
+                "ver",
+                "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\r\n",
+            ),
+        ]);
+        let executor = Executor::new()?;
+        let raw = executor.block_on(read_response(&mut con, "cddb lscat\n"))?;
+        assert_eq!(
+            raw,
+            "210 OK, category list follows (until terminating `.')\r\nrock\r\njazz\r\n"
+        );
+        // the terminator is consumed, the next reply starts on a fresh line
+        let version = executor.block_on(con.ver())?;
+        assert_eq!(version.version, "v1.5.2PL0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_response_single_line_error() -> Result<(), GnuDbError> {
+        let (mut con, _) = scripted(&[(
+            "cddb read",
+            "401 rock 6909aa09 No such CD entry in database.\r\n",
+        )]);
+        let single_match = Match {
+            discid: "6909aa09".to_owned(),
+            category: Category::Rock,
+            ..Default::default()
+        };
+        match Executor::new()?.block_on(con.read(&single_match)) {
+            Err(GnuDbError::NoMatch { code, .. }) => assert_eq!(code, 401),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_response_truncated() -> Result<(), GnuDbError> {
+        let (mut con, _) = scripted(&[("cddb lscat", "210 OK, category list follows\r\nrock\r\n")]);
+        let result = Executor::new()?.block_on(read_response(&mut con, "cddb lscat\n"));
+        assert!(matches!(result, Err(GnuDbError::ConnectionError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_closing_reply_drops_connection() -> Result<(), GnuDbError> {
+        let executor = Executor::new()?;
+        // x3x: the server closes the connection, the reply that follows is never read
+        let (mut con, _) = scripted(&[
+            ("ver", "530 Server error, closing connection.\r\n"),
+            (
+                "ver",
+                "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\r\n",
+            ),
+        ]);
+        assert!(executor.block_on(con.ver()).is_err());
+        let result = executor.block_on(con.ver());
+        assert!(matches!(result, Err(GnuDbError::ConnectionError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_closed_connection() -> Result<(), GnuDbError> {
+        let (mut con, _) = scripted(&[]);
+        con.close();
+        let result = Executor::new()?.block_on(con.ver());
+        assert!(matches!(result, Err(GnuDbError::ConnectionError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::error::GnuDbError;
+
+/// Identity of the client, sent to the server in the hello handshake
+/// ("cddb hello user hostname clientname version") and as "# Submitted via:" in submissions
+/// None of the fields may contain whitespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub user: String,
+    pub hostname: String,
+    pub client_name: String,
+    pub client_version: String,
+}
+
+impl Default for ClientConfig {
+    /// an anonymous user of this crate
+    fn default() -> Self {
+        ClientConfig {
+            user: "anonymous".to_owned(),
+            hostname: "localhost".to_owned(),
+            client_name: env!("CARGO_PKG_NAME").to_owned(),
+            client_version: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// create a client identity, checking that it can be sent in the hello handshake
+    pub fn new(
+        user: &str,
+        hostname: &str,
+        client_name: &str,
+        client_version: &str,
+    ) -> Result<ClientConfig, GnuDbError> {
+        let config = ClientConfig {
+            user: user.to_owned(),
+            hostname: hostname.to_owned(),
+            client_name: client_name.to_owned(),
+            client_version: client_version.to_owned(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// check that every field is a single non-empty word
+    pub fn validate(&self) -> Result<(), GnuDbError> {
+        let fields = [
+            ("user", &self.user),
+            ("hostname", &self.hostname),
+            ("client name", &self.client_name),
+            ("client version", &self.client_version),
+        ];
+        for (name, value) in fields {
+            if value.is_empty() {
+                return Err(GnuDbError::InvalidConfig(format!("{name} is empty")));
+            }
+            if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(GnuDbError::InvalidConfig(format!(
+                    "{name} '{value}' contains whitespace"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// the arguments of the hello command: "user hostname clientname version"
+    pub(crate) fn hello(&self) -> Result<String, GnuDbError> {
+        self.validate()?;
+        Ok(format!(
+            "{} {} {} {}",
+            self.user, self.hostname, self.client_name, self.client_version
+        ))
+    }
+
+    /// the client part of the identity, as written in "# Submitted via:"
+    pub(crate) fn submitted_via(&self) -> String {
+        format!("{} {}", self.client_name, self.client_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hello() -> Result<(), GnuDbError> {
+        let config = ClientConfig::new("joe", "my.host.com", "xmcd", "2.1")?;
+        assert_eq!(config.hello()?, "joe my.host.com xmcd 2.1");
+        assert_eq!(config.submitted_via(), "xmcd 2.1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_is_valid() -> Result<(), GnuDbError> {
+        let config = ClientConfig::default();
+        assert!(config.hello()?.starts_with("anonymous localhost gnudb "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_whitespace() {
+        assert!(ClientConfig::new("joe doe", "host", "app", "1").is_err());
+        assert!(ClientConfig::new("joe", "host", "my app", "1").is_err());
+        assert!(ClientConfig::new("joe", "host\n", "app", "1").is_err());
+        assert!(ClientConfig::new("joe", "host", "app", "").is_err());
+        let config = ClientConfig {
+            client_version: "1.0 beta".to_owned(),
+            ..Default::default()
+        };
+        match config.hello() {
+            Err(GnuDbError::InvalidConfig(message)) => assert!(message.contains("1.0 beta")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
@@ -1,24 +1,309 @@
+use std::io;
+
+/// Errors of the gnudb client
+///
+/// Replies of the server that signal an error carry the numeric CDDB response code and
+/// the message of the status line, so callers can match on the variant instead of the text.
 #[derive(thiserror::Error, Debug)]
 pub enum GnuDbError {
+    /// the connection to the server could not be used, like a closed session or no reachable site
     #[error("Connection Error: {0}")]
     ConnectionError(String),
+    /// a request that cannot be expressed in the protocol, like a submission without discid,
+    /// or an HTTP exchange that failed for another reason than the network, like a TLS error
     #[error("Protocol Error: {0}")]
     ProtocolError(String),
+    /// the server has no entry for the disc (401 on read)
+    #[error("No match: {code} {message}")]
+    NoMatch { code: u16, message: String },
+    /// the server does not allow the command or the connection
+    /// (401 on other commands than read, 409 no handshake, 432)
+    #[error("Permission denied: {code} {message}")]
+    PermissionDenied { code: u16, message: String },
+    /// the server has too many users or too much load right now (433, 434)
+    #[error("Server busy: {code} {message}")]
+    ServerBusy { code: u16, message: String },
+    /// the client exceeded the access limit of the server (417)
+    #[error("Limit exceeded: {code} {message}")]
+    LimitExceeded { code: u16, message: String },
+    /// the entry is already in the database of the server (403 on submit)
+    #[error("Already in cache: {code} {message}")]
+    AlreadyInCache { code: u16, message: String },
+    /// any other error reply of the server
+    #[error("Server error: {code} {message}")]
+    ServerError { code: u16, message: String },
+    /// the server did not answer in time
+    #[error("Timeout: {0}")]
+    Timeout(String),
+    /// reading from or writing to the connection or the local database failed
+    #[error("I/O error: {0}")]
+    Io(#[source] io::Error),
+    /// the HTTP server answered with a status other than 200
+    #[error("HTTP status {0}")]
+    HttpStatus(u16),
+    /// a reply of the server or a record that does not follow the protocol
+    #[error("Malformed response: {reason} in '{line}'")]
+    MalformedResponse { line: String, reason: String },
+    #[error("Invalid category: {0}")]
+    InvalidCategory(String),
+    #[error("Invalid client config: {0}")]
+    InvalidConfig(String),
+    #[error("Unsupported command: {0}")]
+    Unsupported(String),
+    #[error("Submission rejected: {code} {message}")]
+    SubmitRejected { code: u16, message: String },
+}
+
+/// The kind of command a reply answers, some response codes mean different things
+/// for different commands, like 401 on read (no such entry) and on submit (permission denied)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    /// the greeting of the server, hello and proto
+    Handshake,
+    Query,
+    Read,
+    Submit,
+    Other,
+}
+
+impl Command {
+    /// the kind of a CDDBP command line, or of the cmd parameter of an HTTP request
+    pub(crate) fn of(cmd: &str) -> Command {
+        let mut words = cmd.split_whitespace().map(str::to_ascii_lowercase);
+        match (words.next().as_deref(), words.next().as_deref()) {
+            (Some("cddb"), Some("hello")) | (Some("proto"), _) => Command::Handshake,
+            (Some("cddb"), Some("query")) => Command::Query,
+            (Some("cddb"), Some("read")) => Command::Read,
+            (Some("cddb"), Some("write")) => Command::Submit,
+            _ => Command::Other,
+        }
+    }
+}
+
+impl GnuDbError {
+    /// classify the status line of an error reply of the server to the given command, like
+    /// "401 rock 6909aa09 No such CD entry in database."
+    pub(crate) fn from_status(status: &str, command: Command) -> GnuDbError {
+        let status = status.trim_end();
+        let (code, message) = status.split_once(' ').unwrap_or((status, ""));
+        let Ok(code) = code.parse::<u16>() else {
+            return GnuDbError::malformed(status, "missing response code");
+        };
+        let message = message.to_owned();
+        match (code, command) {
+            (401, Command::Read) => GnuDbError::NoMatch { code, message },
+            (401 | 409 | 432, _) => GnuDbError::PermissionDenied { code, message },
+            (417, _) => GnuDbError::LimitExceeded { code, message },
+            (433 | 434, _) => GnuDbError::ServerBusy { code, message },
+            (403, Command::Submit) => GnuDbError::AlreadyInCache { code, message },
+            _ => GnuDbError::ServerError { code, message },
+        }
+    }
+
+    pub(crate) fn malformed(line: &str, reason: impl Into<String>) -> GnuDbError {
+        GnuDbError::MalformedResponse {
+            line: line.to_owned(),
+            reason: reason.into(),
+        }
+    }
+
+    /// the CDDB response code of an error reply of the server, or the HTTP status
+    #[must_use]
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            GnuDbError::NoMatch { code, .. }
+            | GnuDbError::PermissionDenied { code, .. }
+            | GnuDbError::ServerBusy { code, .. }
+            | GnuDbError::LimitExceeded { code, .. }
+            | GnuDbError::AlreadyInCache { code, .. }
+            | GnuDbError::ServerError { code, .. }
+            | GnuDbError::SubmitRejected { code, .. }
+            | GnuDbError::HttpStatus(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// true when the same request may succeed when it is sent again later:
+    /// connection and I/O errors, timeouts, a busy or rate limited server,
+    /// the transient server errors 402 and 530, and HTTP 408, 429 and 5xx
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GnuDbError::ConnectionError(_)
+            | GnuDbError::Io(_)
+            | GnuDbError::Timeout(_)
+            | GnuDbError::ServerBusy { .. }
+            | GnuDbError::LimitExceeded { .. } => true,
+            GnuDbError::ServerError { code, .. } => matches!(code, 402 | 530),
+            GnuDbError::HttpStatus(code) => matches!(code, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// true when the server could not be reached or refused to serve us right now,
+    /// so another mirror should be tried:
+    /// connection errors, HTTP 5xx, and the CDDB "no connections allowed"/"limit exceeded" replies
+    pub(crate) fn is_server_unavailable(&self) -> bool {
+        match self {
+            GnuDbError::ConnectionError(_)
+            | GnuDbError::Io(_)
+            | GnuDbError::Timeout(_)
+            | GnuDbError::ServerBusy { .. }
+            | GnuDbError::LimitExceeded { .. }
+            | GnuDbError::PermissionDenied { code: 432, .. } => true,
+            GnuDbError::HttpStatus(code) => (500..=599).contains(code),
+            _ => false,
+        }
+    }
 }
 
-impl From<std::io::Error> for GnuDbError {
-    fn from(err: std::io::Error) -> Self {
-        GnuDbError::ConnectionError(err.to_string())
+impl From<io::Error> for GnuDbError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::TimedOut {
+            GnuDbError::Timeout(err.to_string())
+        } else {
+            GnuDbError::Io(err)
+        }
     }
 }
 
 impl From<ureq::Error> for GnuDbError {
     fn from(err: ureq::Error) -> Self {
         match err {
-            ureq::Error::StatusCode(code) => {
-                GnuDbError::ProtocolError(format!("HTTP status {code}"))
+            ureq::Error::StatusCode(code) => GnuDbError::HttpStatus(code),
+            ureq::Error::Timeout(_) => GnuDbError::Timeout(err.to_string()),
+            ureq::Error::BodyStalled => GnuDbError::Timeout(err.to_string()),
+            // rustls reports TLS failures like an untrusted certificate as invalid data
+            ureq::Error::Io(err) if err.kind() == io::ErrorKind::InvalidData => {
+                GnuDbError::ProtocolError(err.to_string())
             }
-            _ => GnuDbError::ConnectionError(err.to_string()),
+            ureq::Error::Io(err) => err.into(),
+            // the server could not be reached, it may be back later or a mirror may answer
+            ureq::Error::HostNotFound
+            | ureq::Error::ConnectionFailed
+            | ureq::Error::ConnectProxyFailed(_) => GnuDbError::ConnectionError(err.to_string()),
+            // the request cannot be sent as configured, sending it again does not help
+            ureq::Error::BadUri(_)
+            | ureq::Error::Http(_)
+            | ureq::Error::InvalidProxyUrl
+            | ureq::Error::RequireHttpsOnly(_)
+            | ureq::Error::TlsRequired => GnuDbError::InvalidConfig(err.to_string()),
+            // TLS failures like an untrusted certificate, redirect loops and replies that are not HTTP
+            _ => GnuDbError::ProtocolError(err.to_string()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        let err = GnuDbError::from_status(
+            "401 rock 6909aa09 No such CD entry in database.\r\n",
+            Command::Read,
+        );
+        assert!(matches!(err, GnuDbError::NoMatch { code: 401, .. }));
+        assert_eq!(
+            err.to_string(),
+            "No match: 401 rock 6909aa09 No such CD entry in database."
+        );
+        assert!(matches!(
+            GnuDbError::from_status("401 Permission denied.", Command::Submit),
+            GnuDbError::PermissionDenied { code: 401, .. }
+        ));
+        assert!(matches!(
+            GnuDbError::from_status("417 Access limit exceeded", Command::Other),
+            GnuDbError::LimitExceeded { code: 417, .. }
+        ));
+        assert!(matches!(
+            GnuDbError::from_status(
+                "434 No connections allowed: system load too high",
+                Command::Other
+            ),
+            GnuDbError::ServerBusy { code: 434, .. }
+        ));
+        assert!(matches!(
+            GnuDbError::from_status("403 Entry already in database.", Command::Submit),
+            GnuDbError::AlreadyInCache { code: 403, .. }
+        ));
+        // the same code means something else for another command
+        assert!(matches!(
+            GnuDbError::from_status("403 Database entry is corrupt.", Command::Query),
+            GnuDbError::ServerError { code: 403, .. }
+        ));
+        assert!(matches!(
+            GnuDbError::from_status("500 Command syntax error", Command::Other),
+            GnuDbError::ServerError { code: 500, .. }
+        ));
+        assert!(matches!(
+            GnuDbError::from_status("<html>", Command::Other),
+            GnuDbError::MalformedResponse { .. }
+        ));
+    }
+
+    #[test]
+    fn test_command_of() {
+        assert_eq!(Command::of("cddb read rock 6909aa09\n"), Command::Read);
+        assert_eq!(
+            Command::of("CDDB QUERY 6909aa09 9 150 2476"),
+            Command::Query
+        );
+        assert_eq!(
+            Command::of("cddb hello joe my.host.com xmcd 2.1"),
+            Command::Handshake
+        );
+        assert_eq!(Command::of("proto 6"), Command::Handshake);
+        assert_eq!(Command::of("cddb write rock 6909aa09"), Command::Submit);
+        assert_eq!(Command::of("sites"), Command::Other);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(
+            GnuDbError::from_status("433 No connections allowed: 10 users", Command::Other)
+                .is_retryable()
+        );
+        assert!(
+            GnuDbError::from_status("530 Server error, closing connection.", Command::Other)
+                .is_retryable()
+        );
+        assert!(GnuDbError::HttpStatus(503).is_retryable());
+        assert!(GnuDbError::from(io::Error::from(io::ErrorKind::ConnectionReset)).is_retryable());
+        assert!(matches!(
+            GnuDbError::from(io::Error::new(io::ErrorKind::TimedOut, "read timed out")),
+            GnuDbError::Timeout(_)
+        ));
+        assert!(
+            !GnuDbError::from_status("401 rock 6909aa09 No such CD entry.", Command::Read)
+                .is_retryable()
+        );
+        assert!(
+            !GnuDbError::from_status("500 Command syntax error", Command::Other).is_retryable()
+        );
+        assert!(!GnuDbError::HttpStatus(404).is_retryable());
+        assert!(!GnuDbError::InvalidCategory("pop".to_owned()).is_retryable());
+    }
+
+    #[test]
+    fn test_from_ureq_error() {
+        assert!(GnuDbError::from(ureq::Error::HostNotFound).is_retryable());
+        assert!(GnuDbError::from(ureq::Error::ConnectionFailed).is_retryable());
+        assert!(matches!(
+            GnuDbError::from(ureq::Error::StatusCode(503)),
+            GnuDbError::HttpStatus(503)
+        ));
+        let bad_uri = GnuDbError::from(ureq::Error::BadUri("http://".to_owned()));
+        assert!(matches!(bad_uri, GnuDbError::InvalidConfig(_)));
+        assert!(!bad_uri.is_retryable());
+        assert!(matches!(
+            GnuDbError::from(ureq::Error::InvalidProxyUrl),
+            GnuDbError::InvalidConfig(_)
+        ));
+        let redirects = GnuDbError::from(ureq::Error::TooManyRedirects);
+        assert!(!redirects.is_retryable());
+        assert!(!redirects.is_server_unavailable());
+        assert!(!GnuDbError::from(ureq::Error::Tls("certificate not trusted")).is_retryable());
+    }
+}
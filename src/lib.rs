@@ -1,9 +1,15 @@
 #![allow(clippy::missing_errors_doc)]
 //! Crate to get CDDB information from gnudb.org (like cddb.com and freedb.org in the past)
 //!
-//! Right now only login, query and read are implemented, both over HTTP and CDDBP protocol.
-//! All CDDBP I/O is done async using smol.
+//! Login, query and read are implemented, both over HTTP and CDDBP protocol.
+//! The CDDBP connection also supports the informational commands (sites, motd, stat, ver, whom, help, lscat, discid).
+//! New and corrected entries can be submitted over HTTP.
+//! All CDDBP I/O is done async, using smol by default, or tokio with the `runtime-tokio` feature
+//! (disable the default features to leave smol out).
 //! The HTTP functions are synchronous for simplicity, using ureq.
+//! `AsyncHttpClient` runs them on the blocking thread pool of the runtime, with the same methods as `Connection`.
+//! With the `blocking` feature, `blocking::Connection` offers the CDDBP commands without async.
+//! The `CddbClient` trait abstracts over CDDBP, HTTP and the offline `LocalDb`.
 //!
 //! Example HTTP usage:
 //! ```no_run
@@ -43,109 +49,380 @@
 //! });
 //! ```
 
-use log::debug;
-use smol::{io::BufReader, net::TcpStream};
-use std::net::Shutdown;
+use futures_lite::io::{AsyncRead, AsyncWrite, BufReader};
 
+pub use cache::{CachedClient, DiskCache};
+pub use category::Category;
+pub use client::{AsyncHttpClient, ClientBuilder, HttpClient};
+pub use config::ClientConfig;
 use discid::DiscId;
-use error::GnuDbError;
+use error::{Command, GnuDbError};
+pub use local::LocalDb;
+pub use pool::{ConnectionPool, PooledConnection};
+pub use ratelimit::RateLimiter;
+pub use retry::RetryPolicy;
+pub use server::{Motd, ServerStats, ServerVersion, Site};
+pub use transport::{BoxFuture, CddbClient, Transport};
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cache;
+mod category;
 mod cddbp;
+mod client;
+mod config;
 pub mod error;
 mod http;
+mod local;
 mod parser;
+mod pool;
+mod ratelimit;
+mod retry;
+mod runtime;
+mod server;
+mod transport;
+mod waiters;
+mod writer;
 
 use std::time::Duration;
 
-pub(crate) const HELLO_STRING: &str = "ripperx localhost ripperx 4";
-pub(crate) const TIMEOUT: Duration = Duration::from_secs(10);
-
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct Match {
     pub discid: String,
-    pub category: String,
+    pub category: Category,
     pub artist: String,
     pub title: String,
 }
 
-#[derive(Default, Debug)]
+impl Default for Match {
+    /// an empty match, its category is left unknown
+    fn default() -> Self {
+        Match {
+            discid: String::new(),
+            category: Category::Unknown(String::new()),
+            artist: String::new(),
+            title: String::new(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Disc {
     pub title: String,
     pub artist: String,
     pub year: Option<u16>,
     pub genre: Option<String>,
     pub tracks: Vec<Track>,
+    /// total length of the disc in seconds
+    pub length: u32,
+    pub revision: u32,
+    /// all discids this record applies to
+    pub discids: Vec<String>,
+    /// the server software that processed the record ("# Processed by:")
+    pub processed_by: Option<String>,
+    /// the client software that submitted the record ("# Submitted via:")
+    pub submitted_via: Option<String>,
+    /// extended data of the disc (EXTD), like liner notes
+    pub extended_data: String,
+    /// legacy `ID3v1` genre number found in the extended data ("ID3G:")
+    pub id3_genre: Option<u8>,
+    /// preferred play order of the tracks (PLAYORDER)
+    pub play_order: Vec<u32>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Track {
     pub number: u32,
     pub title: String,
     pub artist: String,
     pub duration: u64,
     pub composer: Option<String>,
+    /// offset of the track on the disc in frames (1/75th of a second)
+    pub offset: u32,
+    /// extended data of the track (`EXTTn`), like per-track comments
+    pub extended: String,
+}
+
+/// Options for parsing xmcd records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// split "Track Artist / Track Title" track titles on compilations into `Track::artist`
+    /// and `Track::title`; turn off for albums whose titles legitimately contain " / "
+    pub split_track_artists: bool,
+    /// also treat a disc as a compilation when every track title contains " / ",
+    /// not only when its artist is "Various" or "Various Artists"
+    pub detect_compilations: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            split_track_artists: true,
+            detect_compilations: false,
+        }
+    }
+}
+
+impl Disc {
+    /// serialize the disc into an xmcd record, as used by the CDDB read and submit commands
+    #[must_use]
+    pub fn to_xmcd(&self) -> String {
+        writer::write_xmcd(self)
+    }
+
+    /// take the track offsets, disc length and discid from the TOC of a physical disc
+    /// the discid becomes the first of the discids, missing tracks are added with an empty title
+    pub fn set_toc(&mut self, discid: &DiscId) {
+        let toc: Vec<discid::Track> = discid.tracks().collect();
+        self.tracks.resize_with(toc.len(), Track::default);
+        for (idx, (track, toc_track)) in self.tracks.iter_mut().zip(&toc).enumerate() {
+            track.number = u32::try_from(idx).unwrap_or_default() + 1;
+            track.offset = u32::try_from(toc_track.offset).unwrap_or_default();
+            track.duration = u64::try_from(toc_track.sectors).unwrap_or_default() / 75;
+        }
+        self.length = u32::try_from(discid.sectors()).unwrap_or_default() / 75;
+        let id = discid.freedb_id();
+        self.discids.retain(|other| *other != id);
+        self.discids.insert(0, id);
+    }
+
+    /// check whether this record describes the physical disc with the given TOC:
+    /// the discid, the track offsets and the disc length must all match
+    #[must_use]
+    pub fn matches_toc(&self, discid: &DiscId) -> bool {
+        if !self.discids.contains(&discid.freedb_id()) {
+            return false;
+        }
+        let length = u32::try_from(discid.sectors()).unwrap_or_default() / 75;
+        let offsets = discid
+            .tracks()
+            .map(|track| u32::try_from(track.offset).unwrap_or_default());
+        self.length == length
+            && self.tracks.len() == discid.tracks().count()
+            && self.tracks.iter().map(|track| track.offset).eq(offsets)
+    }
+
+    /// increment the revision, as required when submitting a correction of an existing entry
+    pub fn bump_revision(&mut self) {
+        self.revision = self.revision.saturating_add(1);
+    }
+}
+
+/// Submit-Mode header of a submission
+/// `Test` lets the server validate the entry without adding it to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitMode {
+    Test,
+    Submit,
+}
+
+impl SubmitMode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            SubmitMode::Test => "test",
+            SubmitMode::Submit => "submit",
+        }
+    }
+}
+
+/// A disc entry to submit, see `http_submit`
+#[derive(Debug)]
+pub struct Submission<'a> {
+    pub category: Category,
+    pub discid: &'a DiscId,
+    pub disc: &'a Disc,
+    pub email: &'a str,
+    pub mode: SubmitMode,
+}
+
+/// Reply of the server to an accepted submission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitResponse {
+    pub code: u16,
+    pub message: String,
 }
 
 /// HTTP query to a `GNUDb` server for a given discid
 /// returns a vector of matches or an error
-/// Every query creates a new connection
+/// Every query creates a new connection, use `ClientBuilder::http` for any other settings
 pub fn http_query(host: &str, port: u16, discid: &DiscId) -> Result<Vec<Match>, GnuDbError> {
-    let cmd = parser::create_query_cmd(discid)?;
-    let cmd = cmd.trim_end();
-    let body = http::http_request(host, port, cmd)?;
-
-    let data = parser::parse_raw_response(&body)?;
-    debug!("HTTP response data:\n{data}");
-    parser::parse_query_response(&data)
+    http_builder(host, port).http()?.query(discid)
 }
 
 /// HTTP read to a `GNUDb` server to fetch a single disc's metadata
-/// Every request creates a new connection
+/// Every request creates a new connection, use `ClientBuilder::http` for any other settings
 pub fn http_read(host: &str, port: u16, single_match: &Match) -> Result<Disc, GnuDbError> {
-    let cmd = parser::create_read_cmd(single_match);
-    let cmd = cmd.trim_end();
-    let body = http::http_request(host, port, cmd)?;
-    let disc = parser::parse_read_response(&body)?;
-    debug!("disc:{disc:?}");
-    Ok(disc)
+    http_builder(host, port).http()?.read(single_match)
+}
+
+/// Submit a disc entry to a `GNUDb` server over HTTP
+/// The disc is serialized into an xmcd record, using the TOC of the given discid
+/// Only the eleven standard categories can be submitted to
+/// Returns the server reply on success, or `GnuDbError::SubmitRejected` when the server refuses the entry
+pub fn http_submit(
+    host: &str,
+    port: u16,
+    submission: &Submission,
+) -> Result<SubmitResponse, GnuDbError> {
+    http_builder(host, port).http()?.submit(submission)
+}
+
+fn http_builder(host: &str, port: u16) -> ClientBuilder {
+    ClientBuilder::new().server(host).http_port(port)
 }
 
 /// Represents a CDDBP connection to a `GNUDb` server
 /// Multiple commands can be sent over the same connection
 pub struct Connection {
-    reader: BufReader<TcpStream>,
+    /// None once the connection is closed
+    reader: Option<BufReader<Box<dyn runtime::Stream>>>,
+    options: ParseOptions,
+    read_timeout: Duration,
+    proto_level: u32,
+    retry: RetryPolicy,
+    /// settings to open the connection again after it broke, None when it cannot be reopened
+    reconnect: Option<Box<ClientBuilder>>,
+    limiter: Option<RateLimiter>,
+    /// the connection to the host counted by the rate limiter, released on close
+    permit: Option<ratelimit::ConnectionPermit>,
 }
 
 impl Connection {
     /// create a new connection to given host:port combination
     pub async fn from_host_port(host: &str, port: u16) -> Result<Connection, GnuDbError> {
-        let s = format!("{host}:{port}");
-        cddbp::connect(s).await
+        ClientBuilder::new()
+            .server(host)
+            .cddbp_port(port)
+            .connect()
+            .await
+    }
+
+    /// log in over an already established byte stream, like a TLS session, a tunnel through
+    /// a proxy or an in-memory pipe in tests, using the identity, protocol level, read timeout and
+    /// parse options of the builder (its server and ports are not used)
+    /// tokio streams can be adapted with `tokio_util::compat`
+    pub async fn from_stream<S>(
+        stream: S,
+        builder: &ClientBuilder,
+    ) -> Result<Connection, GnuDbError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        cddbp::handshake(Box::new(stream), builder).await
     }
 
     /// create a new connection to gnudb.gnudb.org port 8880
     pub async fn new() -> Result<Connection, GnuDbError> {
-        cddbp::connect("gnudb.gnudb.org:8880".to_owned()).await
+        ClientBuilder::new().connect().await
     }
 
     /// query gnudb for a given discid
     /// returns a vector of matches or an error
     pub async fn query(&mut self, discid: &DiscId) -> Result<Vec<Match>, GnuDbError> {
         let query = parser::create_query_cmd(discid)?;
-        cddbp::cddb_query(&mut self.reader, query).await
+        cddbp::cddb_query(self, query).await
     }
 
     /// read all data of a given disc
     pub async fn read(&mut self, single_match: &Match) -> Result<Disc, GnuDbError> {
-        cddbp::cddb_read(&mut self.reader, single_match).await
+        cddbp::cddb_read(self, single_match).await
+    }
+
+    /// list the servers of the database, as known by this server
+    pub async fn sites(&mut self) -> Result<Vec<Site>, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "sites\n").await?;
+        parser::parse_sites_response(&raw)
+    }
+
+    /// get the message of the day
+    pub async fn motd(&mut self) -> Result<Motd, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "motd\n").await?;
+        parser::parse_motd_response(&raw)
     }
 
+    /// get the server status
+    pub async fn stat(&mut self) -> Result<ServerStats, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "stat\n").await?;
+        parser::parse_stat_response(&raw)
+    }
+
+    /// get the server version
+    pub async fn ver(&mut self) -> Result<ServerVersion, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "ver\n").await?;
+        parser::parse_ver_response(&raw)
+    }
+
+    /// list the users connected to the server
+    pub async fn whom(&mut self) -> Result<Vec<String>, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "whom\n").await?;
+        parser::parse_lines_response(&raw)
+    }
+
+    /// get the help text of the server, for all commands or for a single one
+    pub async fn help(&mut self, topic: Option<&str>) -> Result<String, GnuDbError> {
+        let cmd = parser::create_help_cmd(topic)?;
+        let raw = cddbp::cddb_command(self, &cmd).await?;
+        parser::parse_raw_response(&raw, Command::Other)
+    }
+
+    /// list the categories of the database
+    pub async fn lscat(&mut self) -> Result<Vec<Category>, GnuDbError> {
+        let raw = cddbp::cddb_command(self, "cddb lscat\n").await?;
+        parser::parse_lscat_response(&raw)
+    }
+
+    /// let the server calculate the discid for the given track offsets (in frames)
+    /// and total disc length (in seconds)
+    pub async fn discid(
+        &mut self,
+        offsets: &[u32],
+        length_secs: u32,
+    ) -> Result<String, GnuDbError> {
+        let cmd = parser::create_discid_cmd(offsets, length_secs);
+        let raw = cddbp::cddb_command(self, &cmd).await?;
+        parser::parse_discid_response(&raw)
+    }
+
+    /// end the session: the server says goodbye and the connection is closed
+    pub async fn quit(&mut self) -> Result<(), GnuDbError> {
+        let raw = cddbp::cddb_quit(self).await;
+        self.close();
+        parser::parse_quit_response(&raw?)
+    }
+
+    /// set the options used to parse the records returned by `read`
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.options = options;
+    }
+
+    /// close the connection, dropping the stream shuts down the socket
+    /// a closed connection is not opened again by the retry policy
     pub fn close(&mut self) {
-        self.reader.get_mut().shutdown(Shutdown::Both).ok();
+        self.reader = None;
+        self.reconnect = None;
+        self.permit = None;
+    }
+
+    pub(crate) fn reader(
+        &mut self,
+    ) -> Result<&mut BufReader<Box<dyn runtime::Stream>>, GnuDbError> {
+        self.reader
+            .as_mut()
+            .ok_or_else(|| GnuDbError::ConnectionError("connection is closed".to_owned()))
+    }
+
+    /// true when the stream of the connection was closed, by `close`, `quit` or the server
+    /// a connection with a retry policy or a mirror list opens a new one for the next command,
+    /// unless closed by us
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.reader.is_none()
     }
 
-    pub(crate) fn from_reader(reader: BufReader<TcpStream>) -> Self {
-        Connection { reader }
+    /// protocol level of the session, lower than the desired one when the server did not support it
+    #[must_use]
+    pub fn proto_level(&self) -> u32 {
+        self.proto_level
     }
 }
 
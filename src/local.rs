@@ -0,0 +1,273 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use discid::DiscId;
+use log::debug;
+
+use crate::error::GnuDbError;
+use crate::runtime;
+use crate::transport::{BoxFuture, CddbClient};
+use crate::{Category, Disc, Match, ParseOptions, Submission, SubmitMode, SubmitResponse, parser};
+
+/// Offline CDDB database: a directory of xmcd records in the freedb layout,
+/// one file per disc at `<root>/<category>/<discid>`, like an unpacked freedb/gnudb archive
+/// The files are read on the blocking thread pool of the runtime
+#[derive(Debug, Clone)]
+pub struct LocalDb {
+    root: Arc<PathBuf>,
+    options: ParseOptions,
+}
+
+impl LocalDb {
+    /// open the database in the given directory, nothing is read until the first lookup
+    pub fn new(root: impl Into<PathBuf>) -> LocalDb {
+        LocalDb {
+            root: Arc::new(root.into()),
+            options: ParseOptions::default(),
+        }
+    }
+
+    /// directory of the database
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// set the options used to parse the records returned by `read`
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.options = options;
+    }
+
+    /// store a disc under each of its discids, replacing existing records
+    pub fn insert(&self, category: &Category, disc: &Disc) -> Result<(), GnuDbError> {
+        insert(&self.root, category, disc)
+    }
+}
+
+/// path of the record of a disc, checking that category and discid cannot escape the root
+pub(crate) fn record_path(
+    root: &Path,
+    category: &Category,
+    discid: &str,
+) -> Result<PathBuf, GnuDbError> {
+    category.validate()?;
+    if discid.is_empty() || !discid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(GnuDbError::ProtocolError(format!(
+            "invalid discid '{discid}'"
+        )));
+    }
+    Ok(root.join(category.as_str()).join(discid))
+}
+
+/// read a record, old freedb archives are ISO-8859-1, newer ones UTF-8
+pub(crate) fn read_record(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    Ok(String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect()))
+}
+
+fn query(root: &Path, discid: &str) -> Result<Vec<Match>, GnuDbError> {
+    let mut matches = Vec::new();
+    for category in lscat(root)? {
+        let path = record_path(root, &category, discid)?;
+        let data = match read_record(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let disc = parser::parse_read_response_with(&data, &ParseOptions::default())?;
+        matches.push(Match {
+            discid: discid.to_owned(),
+            category,
+            artist: disc.artist,
+            title: disc.title,
+        });
+    }
+    debug!("{} local matches for {discid}", matches.len());
+    Ok(matches)
+}
+
+fn read(root: &Path, single_match: &Match, options: &ParseOptions) -> Result<Disc, GnuDbError> {
+    parser::parse_read_response_with(&read_raw(root, single_match)?, options)
+}
+
+fn read_raw(root: &Path, single_match: &Match) -> Result<String, GnuDbError> {
+    let path = record_path(root, &single_match.category, &single_match.discid)?;
+    read_record(&path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            // the reply of a server for an unknown entry
+            GnuDbError::NoMatch {
+                code: 401,
+                message: format!(
+                    "{} {} No such CD entry in database.",
+                    single_match.category, single_match.discid
+                ),
+            }
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// the category directories, sorted by name
+fn lscat(root: &Path) -> Result<Vec<Category>, GnuDbError> {
+    let mut categories = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            let category = Category::from_server(name);
+            if category.validate().is_ok() {
+                categories.push(category);
+            }
+        }
+    }
+    categories.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    Ok(categories)
+}
+
+fn insert(root: &Path, category: &Category, disc: &Disc) -> Result<(), GnuDbError> {
+    if disc.discids.is_empty() {
+        return Err(GnuDbError::ProtocolError(
+            "cannot store a disc without discid".to_owned(),
+        ));
+    }
+    let record = disc.to_xmcd();
+    for discid in &disc.discids {
+        let path = record_path(root, category, discid)?;
+        fs::create_dir_all(root.join(category.as_str()))?;
+        fs::write(&path, &record)?;
+        debug!("stored {}", path.display());
+    }
+    Ok(())
+}
+
+impl CddbClient for LocalDb {
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+        let root = Arc::clone(&self.root);
+        let discid = discid.freedb_id();
+        Box::pin(runtime::unblock(move || query(&root, &discid)))
+    }
+
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+        let root = Arc::clone(&self.root);
+        let single_match = single_match.clone();
+        let options = self.options.clone();
+        Box::pin(runtime::unblock(move || {
+            read(&root, &single_match, &options)
+        }))
+    }
+
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        let root = Arc::clone(&self.root);
+        let single_match = single_match.clone();
+        Box::pin(runtime::unblock(move || read_raw(&root, &single_match)))
+    }
+
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        let root = Arc::clone(&self.root);
+        Box::pin(runtime::unblock(move || lscat(&root)))
+    }
+
+    /// store the disc with the TOC of the submission, a test submission only checks that it can be stored
+    fn submit<'a>(
+        &'a mut self,
+        submission: &'a Submission<'a>,
+    ) -> BoxFuture<'a, Result<SubmitResponse, GnuDbError>> {
+        let category = submission.category.clone();
+        let mode = submission.mode;
+        let mut disc = submission.disc.clone();
+        disc.set_toc(submission.discid);
+        Box::pin(async move {
+            if !category.is_standard() {
+                return Err(GnuDbError::InvalidCategory(category.to_string()));
+            }
+            if mode == SubmitMode::Submit {
+                let root = Arc::clone(&self.root);
+                runtime::unblock(move || insert(&root, &category, &disc)).await?;
+            }
+            Ok(SubmitResponse {
+                code: 200,
+                message: "OK, submission has been stored.".to_owned(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{sample_disc, temp_root};
+
+    #[test]
+    fn test_insert_query_read() -> Result<(), GnuDbError> {
+        let root = temp_root("roundtrip");
+        let db = LocalDb::new(&root);
+        let mut disc = sample_disc();
+        disc.discids = vec!["6909aa09".to_owned(), "6909aa0a".to_owned()];
+        db.insert(&Category::Rock, &disc)?;
+        db.insert(&Category::Misc, &disc)?;
+        assert!(root.join("rock").join("6909aa0a").is_file());
+
+        let matches = query(&root, "6909aa09")?;
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].category, Category::Misc);
+        assert_eq!(matches[1].category, Category::Rock);
+        assert_eq!(matches[1].title, "Dire Straits");
+        assert!(query(&root, "12345678")?.is_empty());
+
+        let disc = read(&root, &matches[1], &ParseOptions::default())?;
+        assert_eq!(disc.tracks[0].title, "Down to the waterline");
+        assert_eq!(lscat(&root)?, vec![Category::Misc, Category::Rock]);
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_missing_and_latin1() -> Result<(), GnuDbError> {
+        let root = temp_root("latin1");
+        fs::create_dir_all(root.join("jazz"))?;
+        fs::write(
+            root.join("jazz").join("0a0b0c0d"),
+            b"# xmcd\nDISCID=0a0b0c0d\nDTITLE=Art\xe9 / Caf\xe9\nTTITLE0=One\n",
+        )?;
+        let single_match = Match {
+            discid: "0a0b0c0d".to_owned(),
+            category: Category::Jazz,
+            ..Default::default()
+        };
+        let disc = read(&root, &single_match, &ParseOptions::default())?;
+        assert_eq!(disc.artist, "Arté");
+        assert_eq!(disc.title, "Café");
+
+        let missing = Match {
+            category: Category::Rock,
+            ..single_match
+        };
+        match read(&root, &missing, &ParseOptions::default()) {
+            Err(GnuDbError::NoMatch { code, .. }) => assert_eq!(code, 401),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_path_escape() {
+        let root = Path::new("/db");
+        assert!(record_path(root, &Category::Rock, "../../etc").is_err());
+        assert!(record_path(root, &Category::Unknown("..".to_owned()), "6909aa09").is_err());
+        assert_eq!(
+            record_path(root, &Category::Rock, "6909aa09").unwrap(),
+            Path::new("/db/rock/6909aa09")
+        );
+    }
+}
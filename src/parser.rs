@@ -1,8 +1,13 @@
+use std::collections::BTreeMap;
+
 use discid::DiscId;
 use log::debug;
 
-use crate::error::GnuDbError;
-use crate::{Disc, Match, Track};
+use crate::error::{Command, GnuDbError};
+use crate::{
+    Category, Disc, Match, Motd, ParseOptions, ServerStats, ServerVersion, Site, SubmitResponse,
+    Track,
+};
 
 pub(crate) fn create_query_cmd(discid: &DiscId) -> Result<String, GnuDbError> {
     let count = discid.last_track_num() - discid.first_track_num() + 1;
@@ -22,27 +27,68 @@ pub(crate) fn create_query_cmd(discid: &DiscId) -> Result<String, GnuDbError> {
     Ok(query)
 }
 
-pub(crate) fn create_read_cmd(single_match: &Match) -> String {
-    format!(
+pub(crate) fn create_read_cmd(single_match: &Match) -> Result<String, GnuDbError> {
+    single_match.category.validate()?;
+    Ok(format!(
         "cddb read {} {}\n",
         single_match.category, single_match.discid
+    ))
+}
+
+/// server side discid calculation: discid ntrks off1 off2 ... nsecs
+pub(crate) fn create_discid_cmd(offsets: &[u32], length_secs: u32) -> String {
+    let offsets: Vec<String> = offsets.iter().map(u32::to_string).collect();
+    format!(
+        "discid {} {} {length_secs}\n",
+        offsets.len(),
+        offsets.join(" ")
     )
 }
 
-/// parse the raw response from the server according to the protocol
-pub(crate) fn parse_raw_response(raw: &str) -> Result<String, GnuDbError> {
+/// set the protocol level, or ask for the current and supported level without one
+pub(crate) fn create_proto_cmd(level: Option<u32>) -> String {
+    match level {
+        Some(level) => format!("proto {level}\n"),
+        None => "proto\n".to_owned(),
+    }
+}
+
+pub(crate) fn create_help_cmd(topic: Option<&str>) -> Result<String, GnuDbError> {
+    match topic {
+        Some(topic) if topic.chars().any(char::is_control) => Err(GnuDbError::ProtocolError(
+            format!("invalid help topic '{topic}'"),
+        )),
+        Some(topic) => Ok(format!("help {topic}\n")),
+        None => Ok("help\n".to_owned()),
+    }
+}
+
+/// the status line of a raw response, without the line terminator
+fn status_line(raw: &str) -> &str {
+    raw.lines().next().unwrap_or("").trim_end()
+}
+
+/// the message of the status line of a raw response, without the code
+fn status_message(raw: &str) -> &str {
+    let status = status_line(raw);
+    status.split_once(' ').map_or("", |(_, message)| message)
+}
+
+/// parse the raw response from the server to the given kind of command according to the protocol
+pub(crate) fn parse_raw_response(raw: &str, command: Command) -> Result<String, GnuDbError> {
     let (status, rest) = match raw.split_once('\n') {
         Some((head, tail)) => (head, Some(tail)),
         None => (raw, None),
     };
 
     if status.starts_with('4') || status.starts_with('5') {
-        return Err(GnuDbError::ProtocolError(status.to_string()));
+        return Err(GnuDbError::from_status(status, command));
     }
 
-    let second_digit = status.chars().nth(1).ok_or(GnuDbError::ProtocolError(
-        "failed to parse response code".to_string(),
-    ))?;
+    let second_digit = status
+        .chars()
+        .nth(1)
+        .ok_or_else(|| GnuDbError::malformed(status, "failed to parse response code"))?;
     if second_digit == '0' {
         if rest.is_some() {
             return Ok(format!("{status}\n"));
@@ -51,7 +97,7 @@ pub(crate) fn parse_raw_response(raw: &str) -> Result<String, GnuDbError> {
     }
 
     if second_digit != '1' && second_digit != '2' {
-        return Err(GnuDbError::ProtocolError(status.to_string()));
+        return Err(GnuDbError::malformed(status, "unexpected response code"));
     }
 
     let mut data = String::new();
@@ -73,6 +119,203 @@ pub(crate) fn parse_raw_response(raw: &str) -> Result<String, GnuDbError> {
     Ok(data)
 }
 
+/// parse the reply of the submit CGI: 2xx means accepted, 5xx means rejected
+pub(crate) fn parse_submit_response(body: &str) -> Result<SubmitResponse, GnuDbError> {
+    let status = body.lines().next().unwrap_or("").trim();
+    let (code, message) = status.split_once(' ').unwrap_or((status, ""));
+    let code = code.parse::<u16>().map_err(|e| {
+        GnuDbError::malformed(status, format!("failed to parse submit response: {e}"))
+    })?;
+    let message = message.to_owned();
+    match code {
+        200..=299 => Ok(SubmitResponse { code, message }),
+        500..=599 => Err(GnuDbError::SubmitRejected { code, message }),
+        _ => Err(GnuDbError::from_status(status, Command::Submit)),
+    }
+}
+
+/// parse the response of the `sites` command
+/// protocol level 3 and up: site protocol port address latitude longitude description
+/// before level 3: site port latitude longitude description
+pub(crate) fn parse_sites_response(raw: &str) -> Result<Vec<Site>, GnuDbError> {
+    let data = parse_raw_response(raw, Command::Other)?;
+    data.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_site)
+        .collect()
+}
+
+pub(crate) fn parse_site(line: &str) -> Result<Site, GnuDbError> {
+    let err = || GnuDbError::malformed(line, "failed to parse site");
+    let mut split = line.split_whitespace();
+    let host = split.next().ok_or_else(err)?.to_owned();
+    let second = split.next().ok_or_else(err)?;
+    let (protocol, port, address) = if let Ok(port) = second.parse::<u16>() {
+        ("cddbp".to_owned(), port, "-".to_owned())
+    } else {
+        let port = split.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+        let address = split.next().ok_or_else(err)?.to_owned();
+        (second.to_owned(), port, address)
+    };
+    let latitude = parse_coordinate(split.next().ok_or_else(err)?, 'N', 'S').ok_or_else(err)?;
+    let longitude = parse_coordinate(split.next().ok_or_else(err)?, 'E', 'W').ok_or_else(err)?;
+    let description = split.collect::<Vec<&str>>().join(" ");
+    Ok(Site {
+        host,
+        protocol,
+        port,
+        address,
+        latitude,
+        longitude,
+        description,
+    })
+}
+
+/// parse a coordinate like N037.23 or W122.01 into degrees
+fn parse_coordinate(value: &str, positive: char, negative: char) -> Option<f64> {
+    let mut chars = value.chars();
+    let sign = match chars.next()? {
+        c if c == positive => 1.0,
+        c if c == negative => -1.0,
+        _ => return None,
+    };
+    chars
+        .as_str()
+        .parse::<f64>()
+        .ok()
+        .map(|degrees| sign * degrees)
+}
+
+/// parse the response of the `motd` command
+/// the status line reads like: 210 Last modified: 05/31/96 06:31:14 MOTD follows
+pub(crate) fn parse_motd_response(raw: &str) -> Result<Motd, GnuDbError> {
+    let text = parse_raw_response(raw, Command::Other)?;
+    let last_modified = status_message(raw)
+        .strip_prefix("Last modified:")
+        .map(|rest| rest.split(" MOTD").next().unwrap_or(rest).trim().to_owned())
+        .filter(|value| !value.is_empty());
+    Ok(Motd {
+        last_modified,
+        text,
+    })
+}
+
+/// parse the response of the `ver` command, which can be a single line or a list
+pub(crate) fn parse_ver_response(raw: &str) -> Result<ServerVersion, GnuDbError> {
+    let data = parse_raw_response(raw, Command::Other)?;
+    let text = if status_line(raw).starts_with("200") {
+        status_message(raw).to_owned()
+    } else {
+        data.trim_end().to_owned()
+    };
+    let mut split = text.split_whitespace();
+    let name = split.next().unwrap_or_default().to_owned();
+    let version = split.next().unwrap_or_default().to_owned();
+    Ok(ServerVersion {
+        name,
+        version,
+        text,
+    })
+}
+
+/// parse the response of the `stat` command
+pub(crate) fn parse_stat_response(raw: &str) -> Result<ServerStats, GnuDbError> {
+    let data = parse_raw_response(raw, Command::Other)?;
+    let mut stats = ServerStats::default();
+    let mut by_category = false;
+    for line in data.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let indented = line.starts_with([' ', '\t']);
+        let key = key.trim();
+        let value = value.trim();
+        if !indented {
+            by_category = key == "Database entries by category";
+        }
+        if by_category && indented {
+            stats
+                .entries_by_category
+                .push((Category::from_server(key), parse_number(key, value)?));
+            continue;
+        }
+        match key {
+            "current proto" => stats.current_proto = parse_number(key, value)?,
+            "max proto" => stats.max_proto = parse_number(key, value)?,
+            "interface" => value.clone_into(&mut stats.interface),
+            "gets" => stats.gets = value == "yes",
+            "puts" => stats.puts = value == "yes",
+            "updates" => stats.updates = value == "yes",
+            "posting" => stats.posting = value == "yes",
+            "validation" => value.clone_into(&mut stats.validation),
+            "quotes" => stats.quotes = value == "yes",
+            "strip ext" => stats.strip_ext = value == "yes",
+            "secure" => stats.secure = value == "yes",
+            "current users" => stats.current_users = parse_number(key, value)?,
+            "max users" => stats.max_users = parse_number(key, value)?,
+            "Database entries" => stats.database_entries = parse_number(key, value)?,
+            _ => debug!("ignoring stat line '{line}'"),
+        }
+    }
+    Ok(stats)
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, GnuDbError> {
+    value
+        .parse()
+        .map_err(|_| GnuDbError::malformed(value, format!("failed to parse {key}")))
+}
+
+/// parse a response that is a list of lines, like `whom`
+pub(crate) fn parse_lines_response(raw: &str) -> Result<Vec<String>, GnuDbError> {
+    let data = parse_raw_response(raw, Command::Other)?;
+    Ok(data
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// parse the response of the `cddb lscat` command
+pub(crate) fn parse_lscat_response(raw: &str) -> Result<Vec<Category>, GnuDbError> {
+    Ok(parse_lines_response(raw)?
+        .iter()
+        .map(|name| Category::from_server(name))
+        .collect())
+}
+
+/// parse the response of the `discid` command: 200 Disc ID is 03015501
+pub(crate) fn parse_discid_response(raw: &str) -> Result<String, GnuDbError> {
+    parse_raw_response(raw, Command::Other)?;
+    status_message(raw)
+        .split_whitespace()
+        .last()
+        .map(str::to_owned)
+        .ok_or_else(|| GnuDbError::malformed(status_line(raw), "failed to parse discid response"))
+}
+
+/// parse the response of the `proto` command without level, returns the highest supported level:
+/// 200 CDDB protocol level: current 1, supported 6
+pub(crate) fn parse_proto_response(raw: &str) -> Result<u32, GnuDbError> {
+    parse_raw_response(raw, Command::Other)?;
+    let message = status_message(raw);
+    message
+        .split_once("supported")
+        .and_then(|(_, level)| level.trim().parse().ok())
+        .ok_or_else(|| GnuDbError::malformed(status_line(raw), "failed to parse proto response"))
+}
+
+/// parse the response of the `quit` command: 230 hostname Closing connection.  Goodbye.
+pub(crate) fn parse_quit_response(raw: &str) -> Result<(), GnuDbError> {
+    let status = status_line(raw);
+    if status.starts_with("230") {
+        Ok(())
+    } else {
+        Err(GnuDbError::from_status(status, Command::Other))
+    }
+}
+
 pub(crate) fn parse_query_response(response: &str) -> Result<Vec<Match>, GnuDbError> {
     let mut matches: Vec<Match> = Vec::new();
     for line in response.lines() {
@@ -80,19 +323,19 @@ pub(crate) fn parse_query_response(response: &str) -> Result<Vec<Match>, GnuDbEr
             // exact match
             let mut split = line.splitn(4, ' ');
             let _code = split.next();
-            let category = split.next().ok_or(GnuDbError::ProtocolError(
-                "failed to parse exact match category".to_owned(),
-            ))?;
-            let discid = split.next().ok_or(GnuDbError::ProtocolError(
-                "failed to parse exact match discid".to_owned(),
-            ))?;
-            let remainder = split.next().ok_or(GnuDbError::ProtocolError(
-                "failed to parse exact match remainder".to_owned(),
-            ))?;
+            let category = split.next().ok_or_else(|| {
+                GnuDbError::malformed(line, "failed to parse exact match category")
+            })?;
+            let discid = split
+                .next()
+                .ok_or_else(|| GnuDbError::malformed(line, "failed to parse exact match discid"))?;
+            let remainder = split.next().ok_or_else(|| {
+                GnuDbError::malformed(line, "failed to parse exact match remainder")
+            })?;
             let (artist, title) = split_artist_title(remainder)?;
             let m = Match {
                 discid: discid.to_owned(),
-                category: category.to_owned(),
+                category: Category::from_server(category),
                 title,
                 artist,
             };
@@ -115,19 +358,19 @@ pub(crate) fn parse_query_response(response: &str) -> Result<Vec<Match>, GnuDbEr
 /// parse a line of inexact matches
 pub(crate) fn parse_matches(line: &str) -> Result<Match, GnuDbError> {
     let mut split = line.splitn(3, ' ');
-    let category = split.next().ok_or(GnuDbError::ProtocolError(
-        "failed to parse category".to_owned(),
-    ))?;
+    let category = split
+        .next()
+        .ok_or_else(|| GnuDbError::malformed(line, "failed to parse category"))?;
     let id = split
         .next()
-        .ok_or(GnuDbError::ProtocolError("failed to parse id".to_owned()))?;
-    let remainder = split.next().ok_or(GnuDbError::ProtocolError(
-        "failed to parse remainder".to_owned(),
-    ))?;
+        .ok_or_else(|| GnuDbError::malformed(line, "failed to parse id"))?;
+    let remainder = split
+        .next()
+        .ok_or_else(|| GnuDbError::malformed(line, "failed to parse remainder"))?;
     let (artist, title) = split_artist_title(remainder)?;
     Ok(Match {
         discid: id.to_owned(),
-        category: category.to_owned(),
+        category: Category::from_server(category),
         title,
         artist,
     })
@@ -137,129 +380,310 @@ fn split_artist_title(remainder: &str) -> Result<(String, String), GnuDbError> {
     let (artist, title) = remainder
         .split_once(" / ")
         .or_else(|| remainder.split_once('/'))
-        .ok_or(GnuDbError::ProtocolError(
-            "failed to parse artist/title".to_string(),
-        ))?;
+        .ok_or_else(|| GnuDbError::malformed(remainder, "failed to parse artist/title"))?;
     Ok((artist.trim().to_owned(), title.trim().to_owned()))
 }
 
-/// parse the full response from the CDDB server
-pub(crate) fn parse_read_response(data: &str) -> Result<Disc, GnuDbError> {
-    debug!("{data}");
-    let mut disc = Disc {
-        ..Default::default()
-    };
-    let mut reading_offsets = false;
-    let mut track_offsets: Vec<u64> = Vec::new();
-    let mut disc_length_secs: Option<u64> = None;
-    for line in data.lines() {
+/// state of the "# ..." comment header of an xmcd record
+#[derive(Default)]
+struct Header {
+    reading_offsets: bool,
+    track_offsets: Vec<u32>,
+    disc_length_secs: Option<u32>,
+    revision: u32,
+    processed_by: Option<String>,
+    submitted_via: Option<String>,
+}
+
+impl Header {
+    /// parse a header comment line, returns false if the line is not part of the header
+    fn parse_line(&mut self, line: &str) -> bool {
         if line.starts_with("# Track frame offsets:") {
-            reading_offsets = true;
-            continue;
+            self.reading_offsets = true;
+            return true;
         }
 
-        if reading_offsets {
+        if self.reading_offsets {
             if let Some(value) = line.strip_prefix('#') {
                 let trimmed = value.trim();
                 if trimmed.is_empty() {
-                    continue;
+                    return true;
                 }
-                if let Ok(offset) = trimmed.parse::<u64>() {
-                    track_offsets.push(offset);
-                    continue;
+                if let Ok(offset) = trimmed.parse::<u32>() {
+                    self.track_offsets.push(offset);
+                    return true;
                 }
             }
-            reading_offsets = false;
+            self.reading_offsets = false;
         }
 
         if let Some(value) = line.strip_prefix("# Disc length:") {
-            disc_length_secs = value
+            self.disc_length_secs = value
                 .split_whitespace()
                 .next()
-                .and_then(|token| token.parse::<u64>().ok());
-            continue;
+                .and_then(|token| token.parse::<u32>().ok());
+            return true;
         }
 
-        if let Some(value) = line.strip_prefix("DTITLE=") {
-            let mut split = value.splitn(2, '/');
-            let first = split.next().unwrap_or("").trim();
-            if let Some(rest) = split.next() {
-                first.clone_into(&mut disc.artist);
-                rest.trim().clone_into(&mut disc.title);
-            } else {
-                first.clone_into(&mut disc.title);
+        if let Some(value) = line.strip_prefix("# Revision:") {
+            let value = value.trim();
+            match value.parse::<u32>() {
+                Ok(revision) => self.revision = revision,
+                Err(e) => debug!("failed to parse revision '{value}': {e}"),
             }
+            return true;
         }
-        if let Some(value) = line.strip_prefix("DYEAR=") {
-            let value = value.trim();
-            if !value.is_empty() {
-                match value.parse::<u16>() {
-                    Ok(year) => disc.year = Some(year),
-                    Err(e) => {
-                        debug!("failed to parse DYEAR '{value}': {e}");
-                    }
+
+        if let Some(value) = line.strip_prefix("# Processed by:") {
+            self.processed_by = non_empty(value);
+            return true;
+        }
+
+        if let Some(value) = line.strip_prefix("# Submitted via:") {
+            self.submitted_via = non_empty(value);
+            return true;
+        }
+        false
+    }
+}
+
+/// keyword values of an xmcd record
+/// a keyword may be repeated over several lines, the values are concatenated
+#[derive(Default)]
+struct Fields {
+    discids: Vec<String>,
+    dtitle: String,
+    dyear: String,
+    dgenre: String,
+    ttitles: BTreeMap<u32, String>,
+    extd: String,
+    extts: BTreeMap<u32, String>,
+    playorder: String,
+}
+
+impl Fields {
+    fn parse_line(&mut self, line: &str) -> Result<(), GnuDbError> {
+        let Some((key, value)) = line.split_once('=') else {
+            return Ok(());
+        };
+        match key {
+            "DISCID" => self.discids.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_owned),
+            ),
+            "DTITLE" => self.dtitle.push_str(value),
+            "DYEAR" => self.dyear.push_str(value),
+            "DGENRE" => self.dgenre.push_str(value),
+            "EXTD" => self.extd.push_str(value),
+            "PLAYORDER" => self.playorder.push_str(value),
+            _ => {
+                if let Some(index) = key.strip_prefix("TTITLE") {
+                    let index = parse_index("TTITLE", index)?;
+                    self.ttitles.entry(index).or_default().push_str(value);
+                } else if let Some(index) = key.strip_prefix("EXTT") {
+                    let index = parse_index("EXTT", index)?;
+                    self.extts.entry(index).or_default().push_str(value);
+                } else {
+                    debug!("ignoring unknown keyword {key}");
                 }
             }
         }
-        if let Some(value) = line.strip_prefix("DGENRE=") {
-            let value = value.trim();
-            if !value.is_empty() {
-                disc.genre = Some(value.to_owned());
+        Ok(())
+    }
+}
+
+fn parse_index(key: &str, index: &str) -> Result<u32, GnuDbError> {
+    index
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| GnuDbError::malformed(index, format!("failed to parse {key} index: {e}")))
+}
+
+/// parse the full response from the CDDB server
+pub(crate) fn parse_read_response_with(
+    data: &str,
+    options: &ParseOptions,
+) -> Result<Disc, GnuDbError> {
+    debug!("{data}");
+    let mut header = Header::default();
+    let mut fields = Fields::default();
+    for line in data.lines() {
+        if header.parse_line(line) || line.starts_with('#') {
+            continue;
+        }
+        fields.parse_line(line)?;
+    }
+
+    let mut disc = Disc {
+        discids: fields.discids,
+        ..Default::default()
+    };
+    let dtitle = unescape(&fields.dtitle);
+    // "artist / title", like the writer produces; a bare '/' only when there is no " / ",
+    // so an artist like "AC/DC" stays whole
+    if let Some((artist, title)) = dtitle.split_once(" / ").or_else(|| dtitle.split_once('/')) {
+        artist.trim().clone_into(&mut disc.artist);
+        title.trim().clone_into(&mut disc.title);
+    } else {
+        dtitle.trim().clone_into(&mut disc.title);
+    }
+
+    let dyear = unescape(&fields.dyear);
+    let dyear = dyear.trim();
+    if !dyear.is_empty() {
+        match dyear.parse::<u16>() {
+            Ok(year) => disc.year = Some(year),
+            Err(e) => {
+                debug!("failed to parse DYEAR '{dyear}': {e}");
             }
         }
-        // since we use protocol level 6, we should get the year/genre via DYEAR and DGENRE, and these should come before EXTD
-        // this is as a fallback
-        if disc.year.is_none()
-            && line.starts_with("EXTD")
-            && let Some(pos) = line.find("YEAR:")
-        {
-            let value = line[(pos + "YEAR:".len())..]
-                .split_whitespace()
-                .next()
-                .ok_or(GnuDbError::ProtocolError(
-                    "failed to parse EXTD YEAR".to_owned(),
-                ))?;
-            disc.year = Some(value.parse::<u16>().map_err(|e| {
-                GnuDbError::ProtocolError(format!("failed to parse EXTD YEAR: {e}"))
-            })?);
+    }
+    disc.genre = non_empty(&unescape(&fields.dgenre));
+    // since we use protocol level 6, we should get the year/genre via DYEAR and DGENRE
+    // this is as a fallback
+    disc.extended_data = unescape(&fields.extd);
+    if disc.year.is_none()
+        && let Some(value) = extd_tag(&disc.extended_data, "YEAR:")
+    {
+        let value = value
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| GnuDbError::malformed(value, "failed to parse EXTD YEAR"))?;
+        disc.year = Some(value.parse::<u16>().map_err(|e| {
+            GnuDbError::malformed(value, format!("failed to parse EXTD YEAR: {e}"))
+        })?);
+    }
+    if let Some(value) =
+        extd_tag(&disc.extended_data, "ID3G:").and_then(|value| value.split_whitespace().next())
+    {
+        match value.parse::<u8>() {
+            Ok(genre) => disc.id3_genre = Some(genre),
+            Err(e) => debug!("failed to parse EXTD ID3G '{value}': {e}"),
         }
+    }
+    disc.play_order = parse_play_order(&fields.playorder);
 
-        if line.starts_with("TTITLE") {
-            let rest = line
-                .strip_prefix("TTITLE")
-                .ok_or(GnuDbError::ProtocolError(
-                    "failed to parse TTITLE".to_owned(),
-                ))?;
-            let (index_str, title) = rest.split_once('=').ok_or(GnuDbError::ProtocolError(
-                "failed to parse TTITLE value".to_owned(),
-            ))?;
-            let index = index_str.trim().parse::<u32>().map_err(|e| {
-                GnuDbError::ProtocolError(format!("failed to parse TTITLE index: {e}"))
-            })?;
-            let mut track = Track {
-                ..Default::default()
-            };
-            track.number = index + 1; // tracks are 0 based in CDDB/GNUDB
-            title.clone_into(&mut track.title);
-            track.artist.clone_from(&disc.artist);
-            disc.tracks.push(track);
-        }
+    for (index, title) in fields.ttitles {
+        disc.tracks.push(Track {
+            number: index + 1, // tracks are 0 based in CDDB/GNUDB
+            title: unescape(&title),
+            artist: disc.artist.clone(),
+            extended: fields
+                .extts
+                .get(&index)
+                .map(|value| unescape(value))
+                .unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    if options.split_track_artists {
+        split_track_artists(&mut disc, options.detect_compilations);
     }
-    apply_track_durations(&mut disc.tracks, &track_offsets, disc_length_secs);
+    for (track, offset) in disc.tracks.iter_mut().zip(&header.track_offsets) {
+        track.offset = *offset;
+    }
+    disc.length = header.disc_length_secs.unwrap_or_default();
+    disc.revision = header.revision;
+    disc.processed_by = header.processed_by;
+    disc.submitted_via = header.submitted_via;
+    apply_track_durations(
+        &mut disc.tracks,
+        &header.track_offsets,
+        header.disc_length_secs,
+    );
     Ok(disc)
 }
 
-fn apply_track_durations(tracks: &mut [Track], offsets: &[u64], disc_length_secs: Option<u64>) {
+/// decode the `\n`, `\t` and `\\` escape sequences of an xmcd value,
+/// unknown sequences and a trailing backslash are kept as they are
+pub(crate) fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => {
+                unescaped.push('\\');
+                if other != '\\' {
+                    unescaped.push(other);
+                }
+            }
+            // a trailing backslash is kept
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// on compilations every TTITLE reads "Track Artist / Track Title"
+/// a disc is a compilation when its artist is "Various" / "Various Artists",
+/// or, when `detect` is set, when every track of a multi-track disc contains the separator
+fn split_track_artists(disc: &mut Disc, detect: bool) {
+    let various = ["various", "various artists"].contains(&disc.artist.to_lowercase().as_str());
+    let consistent = detect
+        && disc.tracks.len() > 1
+        && disc.tracks.iter().all(|track| track.title.contains(" / "));
+    if !various && !consistent {
+        return;
+    }
+    for track in &mut disc.tracks {
+        if let Some((artist, title)) = track.title.split_once(" / ") {
+            track.artist = artist.trim().to_owned();
+            track.title = title.trim().to_owned();
+        }
+    }
+}
+
+/// find a legacy tag like "YEAR:" in the EXTD field, returns the text following it
+fn extd_tag<'a>(extd: &'a str, tag: &str) -> Option<&'a str> {
+    let pos = extd.find(tag)?;
+    Some(&extd[(pos + tag.len())..])
+}
+
+/// parse the comma separated track numbers of the PLAYORDER field
+fn parse_play_order(value: &str) -> Vec<u32> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.parse::<u32>() {
+            Ok(number) => Some(number),
+            Err(e) => {
+                debug!("failed to parse PLAYORDER entry '{entry}': {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+fn apply_track_durations(tracks: &mut [Track], offsets: &[u32], disc_length_secs: Option<u32>) {
     if tracks.is_empty() || offsets.len() < tracks.len() {
         return;
     }
-    let total_frames = disc_length_secs.map(|secs| secs.saturating_mul(75));
+    let total_frames = disc_length_secs.map(|secs| u64::from(secs).saturating_mul(75));
 
     for (idx, track) in tracks.iter_mut().enumerate() {
-        let Some(start) = offsets.get(idx).copied() else {
+        let Some(start) = offsets.get(idx).copied().map(u64::from) else {
             break;
         };
-        let end = offsets.get(idx + 1).copied().or(total_frames);
+        let end = offsets
+            .get(idx + 1)
+            .copied()
+            .map(u64::from)
+            .or(total_frames);
         if let Some(end_frames) = end {
             let frames = end_frames.saturating_sub(start);
             track.duration = frames / 75;
@@ -268,7 +692,7 @@ fn apply_track_durations(tracks: &mut [Track], offsets: &[u64], disc_length_secs
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     fn init_logger() {
@@ -278,7 +702,7 @@ mod tests {
     #[test]
     fn test_parse_response_multiline_dotstuff() -> Result<(), GnuDbError> {
         let raw = "211 multiple matches\n..hello\nworld\n.\n";
-        let data = parse_raw_response(raw)?;
+        let data = parse_raw_response(raw, Command::Other)?;
         assert_eq!(data, ".hello\nworld\n");
         Ok(())
     }
@@ -286,7 +710,7 @@ mod tests {
     #[test]
     fn test_parse_response_single_line() -> Result<(), GnuDbError> {
         let raw = "200 OK\n";
-        let data = parse_raw_response(raw)?;
+        let data = parse_raw_response(raw, Command::Other)?;
         assert_eq!(data, "200 OK\n");
         Ok(())
     }
@@ -294,20 +718,23 @@ mod tests {
     #[test]
     fn test_parse_response_error() {
         let raw = "500 fail\n";
-        let err = parse_raw_response(raw).unwrap_err();
+        let err = parse_raw_response(raw, Command::Other).unwrap_err();
         match err {
-            GnuDbError::ProtocolError(_) => {}
-            GnuDbError::ConnectionError(_) => panic!("unexpected error type"),
+            GnuDbError::ServerError { code, message } => {
+                assert_eq!(code, 500);
+                assert_eq!(message, "fail");
+            }
+            other => panic!("unexpected error type: {other:?}"),
         }
     }
 
     #[test]
     fn test_parse_response_4xx_error() {
         let raw = "401 Permission denied\n";
-        let err = parse_raw_response(raw).unwrap_err();
+        let err = parse_raw_response(raw, Command::Other).unwrap_err();
         match err {
-            GnuDbError::ProtocolError(msg) => assert!(msg.contains("401")),
-            GnuDbError::ConnectionError(_) => panic!("unexpected error type"),
+            GnuDbError::PermissionDenied { code, .. } => assert_eq!(code, 401),
+            other => panic!("unexpected error type: {other:?}"),
         }
     }
 
@@ -315,17 +742,19 @@ mod tests {
     fn test_parse_response_3xx_error() {
         // 3xx with second digit not 0/1/2 should error
         let raw = "330 connection closing\n";
-        let err = parse_raw_response(raw).unwrap_err();
+        let err = parse_raw_response(raw, Command::Other).unwrap_err();
         match err {
-            GnuDbError::ProtocolError(_) => {}
-            GnuDbError::ConnectionError(_) => panic!("unexpected error type"),
+            GnuDbError::MalformedResponse { line, .. } => {
+                assert_eq!(line, "330 connection closing")
+            }
+            other => panic!("unexpected error type: {other:?}"),
         }
     }
 
     #[test]
     fn test_parse_response_no_newline() -> Result<(), GnuDbError> {
         let raw = "200 OK";
-        let data = parse_raw_response(raw)?;
+        let data = parse_raw_response(raw, Command::Other)?;
         assert_eq!(data, "200 OK");
         Ok(())
     }
@@ -333,7 +762,7 @@ mod tests {
     #[test]
     fn test_dotstuff_multiple_dots() -> Result<(), GnuDbError> {
         let raw = "210 data\n...\n...test\n.\n";
-        let data = parse_raw_response(raw)?;
+        let data = parse_raw_response(raw, Command::Other)?;
         assert_eq!(data, "..\n..test\n");
         Ok(())
     }
@@ -341,7 +770,7 @@ mod tests {
     #[test]
     fn test_empty_response_body() -> Result<(), GnuDbError> {
         let raw = "210 data\n.\n";
-        let data = parse_raw_response(raw)?;
+        let data = parse_raw_response(raw, Command::Other)?;
         assert_eq!(data, "");
         Ok(())
     }
@@ -364,12 +793,12 @@ mod tests {
             blues ghi789 Artist Three / Album Three\n";
         let matches = parse_query_response(response)?;
         assert_eq!(matches.len(), 3);
-        assert_eq!(matches[0].category, "rock");
+        assert_eq!(matches[0].category, Category::Rock);
         assert_eq!(matches[0].discid, "abc123");
         assert_eq!(matches[0].artist, "Artist One");
         assert_eq!(matches[0].title, "Album One");
-        assert_eq!(matches[1].category, "jazz");
-        assert_eq!(matches[2].category, "blues");
+        assert_eq!(matches[1].category, Category::Jazz);
+        assert_eq!(matches[2].category, Category::Blues);
         Ok(())
     }
 
@@ -379,7 +808,7 @@ mod tests {
         let response = "200 rock abc123 The Artist / The Album\n";
         let matches = parse_query_response(response)?;
         assert_eq!(matches.len(), 1);
-        assert_eq!(matches[0].category, "rock");
+        assert_eq!(matches[0].category, Category::Rock);
         assert_eq!(matches[0].discid, "abc123");
         assert_eq!(matches[0].artist, "The Artist");
         assert_eq!(matches[0].title, "The Album");
@@ -392,7 +821,7 @@ mod tests {
         let m = parse_matches("rock abc123 ARTIST / Recording Title")?;
         assert_eq!(m.artist, "ARTIST");
         assert_eq!(m.title, "Recording Title");
-        assert_eq!(m.category, "rock");
+        assert_eq!(m.category, Category::Rock);
         assert_eq!(m.discid, "abc123");
         Ok(())
     }
@@ -450,15 +879,59 @@ mod tests {
     fn test_create_read_cmd() {
         let m = Match {
             discid: "abc123".to_string(),
-            category: "rock".to_string(),
+            category: Category::Rock,
             artist: "Artist".to_string(),
             title: "Title".to_string(),
         };
         let cmd = create_read_cmd(&m);
-        assert_eq!(cmd, "cddb read rock abc123\n");
+        assert_eq!(cmd.unwrap(), "cddb read rock abc123\n");
+    }
+
+    #[test]
+    fn test_create_read_cmd_invalid_category() {
+        let m = Match {
+            discid: "abc123".to_string(),
+            category: Category::Unknown("rock abc123\nquit".to_string()),
+            ..Default::default()
+        };
+        let err = create_read_cmd(&m).unwrap_err();
+        assert!(matches!(err, GnuDbError::InvalidCategory(_)));
+    }
+
+    #[test]
+    fn test_parse_unknown_category() -> Result<(), GnuDbError> {
+        let m = parse_matches("electronic abc123 Artist / Title")?;
+        assert_eq!(m.category, Category::Unknown("electronic".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_submit_response_ok() -> Result<(), GnuDbError> {
+        let response = parse_submit_response("200 OK, submission has been sent.\n")?;
+        assert_eq!(response.code, 200);
+        assert_eq!(response.message, "OK, submission has been sent.");
+        Ok(())
     }
 
-    const RAMMSTEIN: &str = r"# xmcd
+    #[test]
+    fn test_parse_submit_response_rejected() {
+        let err = parse_submit_response("501 Missing required header information.\n").unwrap_err();
+        match err {
+            GnuDbError::SubmitRejected { code, message } => {
+                assert_eq!(code, 501);
+                assert_eq!(message, "Missing required header information.");
+            }
+            other => panic!("unexpected error type: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_submit_response_garbage() {
+        let err = parse_submit_response("<html>oops</html>").unwrap_err();
+        assert!(matches!(err, GnuDbError::MalformedResponse { .. }));
+    }
+
+    pub(crate) const RAMMSTEIN: &str = r"# xmcd
 #
 # Track frame offsets:
 #    150
@@ -508,7 +981,7 @@ EXTT9=
 EXTT10=
 PLAYORDER=";
 
-    const DIRE_STRAITS: &str = r"# xmcd
+    pub(crate) const DIRE_STRAITS: &str = r"# xmcd
 #
 # Track frame offsets:
 #    150
@@ -555,7 +1028,7 @@ PLAYORDER=";
     #[test]
     fn test_parse() -> Result<(), GnuDbError> {
         init_logger();
-        let disc = parse_read_response(RAMMSTEIN)?;
+        let disc = parse_read_response_with(RAMMSTEIN, &ParseOptions::default())?;
         assert_eq!(disc.year.unwrap(), 2002);
         assert_eq!(disc.title, "(black) Mutter");
         assert_eq!(disc.tracks.len(), 11);
@@ -566,7 +1039,7 @@ PLAYORDER=";
     #[test]
     fn test_track_durations_from_offsets() -> Result<(), GnuDbError> {
         init_logger();
-        let disc = parse_read_response(RAMMSTEIN)?;
+        let disc = parse_read_response_with(RAMMSTEIN, &ParseOptions::default())?;
         let durations: Vec<u64> = disc.tracks.iter().map(|t| t.duration).collect();
         assert_eq!(
             durations,
@@ -575,15 +1048,517 @@ PLAYORDER=";
         Ok(())
     }
 
+    #[test]
+    fn test_parse_header() -> Result<(), GnuDbError> {
+        init_logger();
+        let disc = parse_read_response_with(RAMMSTEIN, &ParseOptions::default())?;
+        let offsets: Vec<u32> = disc.tracks.iter().map(|t| t.offset).collect();
+        assert_eq!(
+            offsets,
+            vec![
+                150, 25_075, 46_501, 70_596, 88_533, 105_910, 125_169, 147_365, 162_906, 190_441,
+                215_174
+            ]
+        );
+        assert_eq!(disc.length, 3186);
+        assert_eq!(disc.revision, 2);
+        assert_eq!(
+            disc.processed_by.as_deref(),
+            Some("cddbd v1.5.1PL2 Copyright (c) Steve Scherf et al.")
+        );
+        assert_eq!(disc.submitted_via.as_deref(), Some("audiograbber 1.83.01"));
+        assert_eq!(disc.discids, vec!["940c700b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_multiple_discids() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DISCID=940c700b,940c700c\nDISCID=940c700d\nDTITLE=Artist / Album\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.discids, vec!["940c700b", "940c700c", "940c700d"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_missing_header() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Artist / Album\nTTITLE0=Track\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.length, 0);
+        assert_eq!(disc.revision, 0);
+        assert!(disc.processed_by.is_none());
+        assert!(disc.submitted_via.is_none());
+        assert!(disc.discids.is_empty());
+        assert_eq!(disc.tracks[0].offset, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_continued_fields() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Wiener Philharmoniker / Beethoven: Symphonie Nr. 9 d-Moll op. 125 \n\
+            DTITLE=\"Choral\"\n\
+            TTITLE0=I. Allegro ma non troppo, \n\
+            TTITLE0=un poco maestoso\n\
+            TTITLE1=II. Molto vivace\n\
+            TTITLE2=III. Adagio molto e cantabile\n\
+            TTITLE2= - Andante moderato\n\
+            TTITLE2= - Tempo I\n\
+            EXTD=Recorded live \n\
+            EXTD=YEAR: 1963\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.artist, "Wiener Philharmoniker");
+        assert_eq!(
+            disc.title,
+            "Beethoven: Symphonie Nr. 9 d-Moll op. 125 \"Choral\""
+        );
+        assert_eq!(disc.year, Some(1963));
+        assert_eq!(disc.tracks.len(), 3);
+        assert_eq!(
+            disc.tracks[0].title,
+            "I. Allegro ma non troppo, un poco maestoso"
+        );
+        assert_eq!(disc.tracks[1].title, "II. Molto vivace");
+        assert_eq!(
+            disc.tracks[2].title,
+            "III. Adagio molto e cantabile - Andante moderato - Tempo I"
+        );
+        assert_eq!(disc.tracks[2].number, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_continued_fields_interleaved() -> Result<(), GnuDbError> {
+        init_logger();
+        // continuation lines of a track may come after other tracks
+        let data = "DTITLE=Artist / Album\nTTITLE0=First \nTTITLE1=Second\nTTITLE0=part\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.tracks.len(), 2);
+        assert_eq!(disc.tracks[0].title, "First part");
+        assert_eq!(disc.tracks[0].number, 1);
+        assert_eq!(disc.tracks[1].title, "Second");
+        Ok(())
+    }
+
+    #[test]
+    fn test_writer_split_fields_are_joined() -> Result<(), GnuDbError> {
+        init_logger();
+        let mut disc = parse_read_response_with(DIRE_STRAITS, &ParseOptions::default())?;
+        let long_title = "Sultans of swing (live at the Hammersmith Odeon) ".repeat(12);
+        disc.tracks[5].title.clone_from(&long_title);
+        disc.title = "Dire Straits ".repeat(30).trim_end().to_owned();
+        let xmcd = disc.to_xmcd();
+        assert!(xmcd.lines().filter(|l| l.starts_with("TTITLE5=")).count() > 1);
+        assert!(xmcd.lines().filter(|l| l.starts_with("DTITLE=")).count() > 1);
+        let reparsed = parse_read_response_with(&xmcd, &ParseOptions::default())?;
+        assert_eq!(reparsed.tracks.len(), 9);
+        assert_eq!(reparsed.tracks[5].title, long_title);
+        assert_eq!(reparsed, disc);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_ttitle_index() {
+        let result = parse_read_response_with(
+            "DTITLE=Artist / Album\nTTITLEx=Track\n",
+            &ParseOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(unescape("a\\nb\\tc\\\\d"), "a\nb\tc\\d");
+        assert_eq!(unescape(""), "");
+    }
+
+    #[test]
+    fn test_unescape_edge_cases() {
+        // a trailing backslash is kept
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+        assert_eq!(unescape("\\"), "\\");
+        // an escaped backslash followed by n is not a newline
+        assert_eq!(unescape("C:\\\\new"), "C:\\new");
+        // unknown sequences are kept verbatim
+        assert_eq!(unescape("AC\\DC \\x"), "AC\\DC \\x");
+        assert_eq!(unescape("\\\\\\"), "\\\\");
+        assert_eq!(unescape("é\\tè"), "é\tè");
+    }
+
+    #[test]
+    fn test_parse_unescapes_values() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Artist / Album\\tDeluxe\n\
+            DGENRE=Rock\\\\Pop\n\
+            TTITLE0=Line one\\nLine two\n\
+            TTITLE1=Ends with backslash\\\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.title, "Album\tDeluxe");
+        assert_eq!(disc.genre.as_deref(), Some("Rock\\Pop"));
+        assert_eq!(disc.tracks[0].title, "Line one\nLine two");
+        assert_eq!(disc.tracks[1].title, "Ends with backslash\\");
+        Ok(())
+    }
+
+    #[test]
+    fn test_escapes_round_trip() -> Result<(), GnuDbError> {
+        init_logger();
+        let mut disc = parse_read_response_with(RAMMSTEIN, &ParseOptions::default())?;
+        disc.tracks[0].title = "Tab\there, newline\nthere, backslash \\n and \\".to_owned();
+        disc.title = "Mutter\\".to_owned();
+        let xmcd = disc.to_xmcd();
+        assert!(xmcd.contains("TTITLE0=Tab\\there, newline\\nthere, backslash \\\\n and \\\\\n"));
+        let reparsed = parse_read_response_with(&xmcd, &ParseOptions::default())?;
+        assert_eq!(reparsed, disc);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_data() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Artist / Album\n\
+            TTITLE0=One\n\
+            TTITLE1=Two\n\
+            EXTD=Liner notes:\\nRecorded in Abbey Road. \n\
+            EXTD=YEAR: 1969 ID3G: 17\n\
+            EXTT0=First take\n\
+            EXTT1=Bonus\\ttrack, \n\
+            EXTT1=remastered\n\
+            EXTT5=no such track\n\
+            PLAYORDER=1,0, 1\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(
+            disc.extended_data,
+            "Liner notes:\nRecorded in Abbey Road. YEAR: 1969 ID3G: 17"
+        );
+        assert_eq!(disc.year, Some(1969));
+        assert_eq!(disc.id3_genre, Some(17));
+        assert_eq!(disc.tracks.len(), 2);
+        assert_eq!(disc.tracks[0].extended, "First take");
+        assert_eq!(disc.tracks[1].extended, "Bonus\ttrack, remastered");
+        assert_eq!(disc.play_order, vec![1, 0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_extended_data() -> Result<(), GnuDbError> {
+        init_logger();
+        let disc = parse_read_response_with(RAMMSTEIN, &ParseOptions::default())?;
+        assert_eq!(disc.extended_data, "");
+        assert!(disc.id3_genre.is_none());
+        assert!(disc.play_order.is_empty());
+        assert!(disc.tracks.iter().all(|t| t.extended.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_id3g_and_play_order() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Artist / Album\nTTITLE0=One\nEXTD=ID3G: rock\nPLAYORDER=0,x,2\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert!(disc.id3_genre.is_none());
+        assert_eq!(disc.play_order, vec![0, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_discid_cmd() {
+        let cmd = create_discid_cmd(&[150, 18_051, 42_248], 2476);
+        assert_eq!(cmd, "discid 3 150 18051 42248 2476\n");
+    }
+
+    #[test]
+    fn test_create_help_cmd() -> Result<(), GnuDbError> {
+        assert_eq!(create_help_cmd(None)?, "help\n");
+        assert_eq!(create_help_cmd(Some("cddb read"))?, "help cddb read\n");
+        assert!(create_help_cmd(Some("read\nquit")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_sites() -> Result<(), GnuDbError> {
+        let raw = "210 OK, site information follows (until terminating `.')\n\
+            gnudb.gnudb.org cddbp 8880 - N000.00 W000.00 GnuDB CDDBP Server\n\
+            gnudb.gnudb.org http 80 /~cddb/cddb.cgi N000.00 W000.00 GnuDB HTTP Server\n\
+            cddb.example.com 888 N037.23 W122.01 Old style, Sunnyvale CA USA\n\
+            .\n";
+        let sites = parse_sites_response(raw)?;
+        assert_eq!(sites.len(), 3);
+        assert_eq!(sites[0].host, "gnudb.gnudb.org");
+        assert_eq!(sites[0].protocol, "cddbp");
+        assert_eq!(sites[0].port, 8880);
+        assert_eq!(sites[0].address, "-");
+        assert_eq!(sites[0].description, "GnuDB CDDBP Server");
+        assert_eq!(sites[1].protocol, "http");
+        assert_eq!(sites[1].port, 80);
+        assert_eq!(sites[1].address, "/~cddb/cddb.cgi");
+        assert_eq!(sites[2].host, "cddb.example.com");
+        assert_eq!(sites[2].protocol, "cddbp");
+        assert_eq!(sites[2].port, 888);
+        assert!((sites[2].latitude - 37.23).abs() < f64::EPSILON);
+        assert!((sites[2].longitude + 122.01).abs() < f64::EPSILON);
+        assert_eq!(sites[2].description, "Old style, Sunnyvale CA USA");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_site() {
+        assert!(parse_site("host cddbp notaport - N0 W0 x").is_err());
+        assert!(parse_site("host cddbp 8880 - X000.00 W000.00 x").is_err());
+        assert!(parse_site("host").is_err());
+    }
+
+    #[test]
+    fn test_parse_sites_unavailable() {
+        assert!(parse_sites_response("401 No site information available.\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_motd() -> Result<(), GnuDbError> {
+        let raw = "210 Last modified: 05/31/96 06:31:14 MOTD follows (until terminating `.')\n\
+            Welcome to gnudb.\n\
+            ..dots are unstuffed\n\
+            .\n";
+        let motd = parse_motd_response(raw)?;
+        assert_eq!(motd.last_modified.as_deref(), Some("05/31/96 06:31:14"));
+        assert_eq!(motd.text, "Welcome to gnudb.\n.dots are unstuffed\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ver() -> Result<(), GnuDbError> {
+        let version = parse_ver_response(
+            "200 cddbd v1.5.2PL0 Copyright (c) 1996-2003 Steve Scherf et al.\n",
+        )?;
+        assert_eq!(version.name, "cddbd");
+        assert_eq!(version.version, "v1.5.2PL0");
+        assert_eq!(
+            version.text,
+            "cddbd v1.5.2PL0 Copyright (c) 1996-2003 Steve Scherf et al."
+        );
+        let version =
+            parse_ver_response("211 OK, version information follows\ngnudbd 2.0\nmore\n.\n")?;
+        assert_eq!(version.name, "gnudbd");
+        assert_eq!(version.version, "2.0");
+        assert_eq!(version.text, "gnudbd 2.0\nmore");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_stat() -> Result<(), GnuDbError> {
+        let raw = "210 OK, status information follows (until terminating `.')\n\
+            Server status:\n\
+            \x20   current proto: 6\n\
+            \x20   max proto: 6\n\
+            \x20   interface: cddbp\n\
+            \x20   gets: no\n\
+            \x20   puts: yes\n\
+            \x20   updates: no\n\
+            \x20   posting: no\n\
+            \x20   validation: accepted\n\
+            \x20   quotes: yes\n\
+            \x20   strip ext: no\n\
+            \x20   secure: yes\n\
+            \x20   current users: 3\n\
+            \x20   max users: 100\n\
+            Database entries: 1234\n\
+            Database entries by category:\n\
+            \x20   rock: 1000\n\
+            \x20   electronic: 234\n\
+            .\n";
+        let stats = parse_stat_response(raw)?;
+        assert_eq!(stats.current_proto, 6);
+        assert_eq!(stats.max_proto, 6);
+        assert_eq!(stats.interface, "cddbp");
+        assert!(!stats.gets);
+        assert!(stats.puts);
+        assert_eq!(stats.validation, "accepted");
+        assert!(stats.quotes);
+        assert!(!stats.strip_ext);
+        assert!(stats.secure);
+        assert_eq!(stats.current_users, 3);
+        assert_eq!(stats.max_users, 100);
+        assert_eq!(stats.database_entries, 1234);
+        assert_eq!(
+            stats.entries_by_category,
+            vec![
+                (Category::Rock, 1000),
+                (Category::Unknown("electronic".to_owned()), 234)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_stat_invalid_number() {
+        let raw = "210 OK\n    max users: lots\n.\n";
+        assert!(parse_stat_response(raw).is_err());
+    }
+
+    #[test]
+    fn test_parse_whom() -> Result<(), GnuDbError> {
+        let users = parse_lines_response("210 OK, user list follows\nalice\nbob\n.\n")?;
+        assert_eq!(users, vec!["alice", "bob"]);
+        assert!(parse_lines_response("401 No user information available.\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lscat() -> Result<(), GnuDbError> {
+        let raw = "210 OK, category list follows (until terminating `.')\n\
+            blues\nclassical\nrock\nelectronic\n.\n";
+        let categories = parse_lscat_response(raw)?;
+        assert_eq!(
+            categories,
+            vec![
+                Category::Blues,
+                Category::Classical,
+                Category::Rock,
+                Category::Unknown("electronic".to_owned())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_discid() -> Result<(), GnuDbError> {
+        assert_eq!(
+            parse_discid_response("200 Disc ID is 03015501\n")?,
+            "03015501"
+        );
+        assert!(parse_discid_response("500 Command syntax error\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_proto() -> Result<(), GnuDbError> {
+        assert_eq!(create_proto_cmd(Some(6)), "proto 6\n");
+        assert_eq!(create_proto_cmd(None), "proto\n");
+        assert_eq!(
+            parse_proto_response("200 CDDB protocol level: current 1, supported 5\n")?,
+            5
+        );
+        assert!(parse_proto_response("200 CDDB protocol level: current 1\n").is_err());
+        assert!(parse_proto_response("500 Command syntax error\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_quit() {
+        assert!(parse_quit_response("230 gnudb Closing connection.  Goodbye.\n").is_ok());
+        assert!(parse_quit_response("500 Unrecognized command.\n").is_err());
+    }
+
+    const VARIOUS: &str = "DTITLE=Various / Now That's What I Call Music 5\n\
+        TTITLE0=Queen / Radio Ga Ga\n\
+        TTITLE1=Nena / 99 Red Balloons\n\
+        TTITLE2=Intro\n\
+        TTITLE3=AC/DC / Back In Black\n";
+
+    #[test]
+    fn test_various_artists() -> Result<(), GnuDbError> {
+        init_logger();
+        let disc = parse_read_response_with(VARIOUS, &ParseOptions::default())?;
+        assert_eq!(disc.artist, "Various");
+        let tracks: Vec<(&str, &str)> = disc
+            .tracks
+            .iter()
+            .map(|t| (t.artist.as_str(), t.title.as_str()))
+            .collect();
+        assert_eq!(
+            tracks,
+            vec![
+                ("Queen", "Radio Ga Ga"),
+                ("Nena", "99 Red Balloons"),
+                ("Various", "Intro"),
+                ("AC/DC", "Back In Black"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_various_artists_spelled_out() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=VARIOUS ARTISTS / Hits\nTTITLE0=Queen / Radio Ga Ga\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.tracks[0].artist, "Queen");
+        assert_eq!(disc.tracks[0].title, "Radio Ga Ga");
+        Ok(())
+    }
+
+    #[test]
+    fn test_consistent_track_separator() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Label / Sampler 2001\n\
+            TTITLE0=Moby / Porcelain\n\
+            TTITLE1=Air / Sexy Boy\n";
+        // only split when asked to, an album may have every title contain " / "
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.tracks[0].artist, "Label");
+        assert_eq!(disc.tracks[0].title, "Moby / Porcelain");
+        let options = ParseOptions {
+            detect_compilations: true,
+            ..Default::default()
+        };
+        let disc = parse_read_response_with(data, &options)?;
+        assert_eq!(disc.artist, "Label");
+        assert_eq!(disc.tracks[0].artist, "Moby");
+        assert_eq!(disc.tracks[0].title, "Porcelain");
+        assert_eq!(disc.tracks[1].artist, "Air");
+        assert_eq!(disc.tracks[1].title, "Sexy Boy");
+        Ok(())
+    }
+
+    #[test]
+    fn test_occasional_slash_is_not_split() -> Result<(), GnuDbError> {
+        init_logger();
+        let data = "DTITLE=Artist / Album\nTTITLE0=Track with / slash\nTTITLE1=Plain\n";
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
+        assert_eq!(disc.tracks[0].artist, "Artist");
+        assert_eq!(disc.tracks[0].title, "Track with / slash");
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_track_artists_opt_out() -> Result<(), GnuDbError> {
+        init_logger();
+        let options = ParseOptions {
+            split_track_artists: false,
+            ..Default::default()
+        };
+        let disc = parse_read_response_with(VARIOUS, &options)?;
+        assert_eq!(disc.tracks[0].artist, "Various");
+        assert_eq!(disc.tracks[0].title, "Queen / Radio Ga Ga");
+        Ok(())
+    }
+
+    #[test]
+    fn test_various_artists_round_trip() -> Result<(), GnuDbError> {
+        init_logger();
+        let disc = parse_read_response_with(VARIOUS, &ParseOptions::default())?;
+        let xmcd = disc.to_xmcd();
+        assert!(xmcd.contains("TTITLE0=Queen / Radio Ga Ga\n"));
+        assert!(xmcd.contains("TTITLE2=Intro\n"));
+        assert_eq!(
+            parse_read_response_with(&xmcd, &ParseOptions::default())?.tracks,
+            disc.tracks
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_extd() -> Result<(), GnuDbError> {
         init_logger();
-        let disc = parse_read_response(DIRE_STRAITS)?;
+        let disc = parse_read_response_with(DIRE_STRAITS, &ParseOptions::default())?;
         assert_eq!(disc.year.unwrap(), 1978);
         assert_eq!(disc.genre.unwrap(), "Rock");
         assert_eq!(disc.tracks.len(), 9);
         assert_eq!(disc.title, "Dire Straits");
         assert_eq!(disc.artist, "DIRE STRAITS");
+        assert_eq!(disc.id3_genre, Some(17));
         Ok(())
     }
 
@@ -592,7 +1567,7 @@ PLAYORDER=";
         init_logger();
         let data =
             "DTITLE=Unknown Artist / Mystery Record\nDYEAR=\nDGENRE=Unknown\nTTITLE0=Track 01\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert!(disc.year.is_none());
         assert_eq!(disc.title, "Mystery Record");
         assert_eq!(disc.artist, "Unknown Artist");
@@ -603,7 +1578,7 @@ PLAYORDER=";
     fn test_invalid_dyear_uses_extd() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Sample Artist / Sample Title\nDYEAR=abcd\nDGENRE=Alt\nTTITLE0=Track 01\nEXTD= YEAR: 1999\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.year, Some(1999));
         assert_eq!(disc.genre.as_deref(), Some("Alt"));
         Ok(())
@@ -614,7 +1589,7 @@ PLAYORDER=";
         init_logger();
         let data =
             "DTITLE=Artist / Title\nDYEAR=2001\nDGENRE=Rock\nTTITLE0=Song\nEXTD= YEAR: 1980\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.year, Some(2001));
         Ok(())
     }
@@ -623,7 +1598,7 @@ PLAYORDER=";
     fn test_tracks_inherit_artist_and_numbering() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Sample Artist / Example Album\nDYEAR=\nTTITLE0=Track Zero\nTTITLE1=Track One\nEXTD= YEAR: 1995\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.artist, "Sample Artist");
         assert_eq!(disc.title, "Example Album");
         assert_eq!(disc.genre, None);
@@ -642,7 +1617,7 @@ PLAYORDER=";
     fn test_dtitle_without_artist() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Just A Title\nDYEAR=2000\nTTITLE0=Track\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.title, "Just A Title");
         assert_eq!(disc.artist, "");
         Ok(())
@@ -652,7 +1627,7 @@ PLAYORDER=";
     fn test_empty_genre() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Artist / Album\nDYEAR=2000\nDGENRE=\nTTITLE0=Track\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert!(disc.genre.is_none());
         Ok(())
     }
@@ -661,7 +1636,7 @@ PLAYORDER=";
     fn test_whitespace_only_genre() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Artist / Album\nDYEAR=2000\nDGENRE=   \nTTITLE0=Track\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert!(disc.genre.is_none());
         Ok(())
     }
@@ -670,7 +1645,7 @@ PLAYORDER=";
     fn test_disc_with_no_tracks() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Artist / Album\nDYEAR=2000\nDGENRE=Rock\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.tracks.len(), 0);
         assert_eq!(disc.title, "Album");
         Ok(())
@@ -680,7 +1655,7 @@ PLAYORDER=";
     fn test_track_with_special_characters() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Artist / Album\nTTITLE0=Track with / slash\nTTITLE1=Track (with) [brackets] & symbols!\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.tracks.len(), 2);
         assert_eq!(disc.tracks[0].title, "Track with / slash");
         assert_eq!(disc.tracks[1].title, "Track (with) [brackets] & symbols!");
@@ -692,7 +1667,7 @@ PLAYORDER=";
         init_logger();
         // Year too large for u16
         let data = "DTITLE=Artist / Album\nDYEAR=99999\nTTITLE0=Track\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         // Should fail to parse and fall through without setting year
         assert!(disc.year.is_none());
         Ok(())
@@ -702,7 +1677,7 @@ PLAYORDER=";
     fn test_extd_year_not_used_when_dyear_valid() -> Result<(), GnuDbError> {
         init_logger();
         let data = "DTITLE=Artist / Album\nDYEAR=2020\nTTITLE0=Track\nEXTD= YEAR: 1999\n";
-        let disc = parse_read_response(data)?;
+        let disc = parse_read_response_with(data, &ParseOptions::default())?;
         assert_eq!(disc.year, Some(2020));
         Ok(())
     }
@@ -0,0 +1,254 @@
+//! Pool of logged in CDDBP connections, see `ConnectionPool`
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use discid::DiscId;
+use log::debug;
+
+use crate::error::GnuDbError;
+use crate::transport::{BoxFuture, CddbClient};
+use crate::waiters::{self, Waiters};
+use crate::{Category, ClientBuilder, Connection, Disc, Match, Site, cddbp, parser};
+
+const DEFAULT_MAX_SIZE: usize = 4;
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_CHECK_AFTER: Duration = Duration::from_secs(10);
+
+/// Bounded pool of CDDBP connections that stay logged in between lookups,
+/// so a service answering many of them does the hello and proto handshake only once per connection
+///
+/// `get` hands out an idle connection, or opens a new one while the pool has fewer than
+/// `max_size` connections, and otherwise waits until one is returned.
+/// A connection goes back to the pool when the `PooledConnection` is dropped,
+/// unless it was closed by `quit`, by an error or by the server (230/530 replies).
+/// Idle connections are dropped after the idle timeout, and checked with `ver` before they are
+/// handed out again once they were idle for a while.
+/// Clones share their connections.
+/// ```no_run
+/// use discid::DiscId;
+/// use gnudb::{ClientBuilder, ConnectionPool};
+///
+/// #[cfg(feature = "runtime-smol")]
+/// smol::block_on(async {
+///     let pool = ConnectionPool::new(ClientBuilder::new()).max_size(2);
+///     let discid = DiscId::read(Some(DiscId::default_device().as_str())).unwrap();
+///     let matches = pool.query(&discid).await.unwrap();
+///     let _disc = pool.read(&matches[0]).await.unwrap();
+///     // or keep a connection for several commands
+///     let mut con = pool.get().await.unwrap();
+///     let _categories = con.lscat().await.unwrap();
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ConnectionPool {
+    builder: Arc<ClientBuilder>,
+    max_size: usize,
+    idle_timeout: Duration,
+    check_after: Duration,
+    shared: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// logged in connections that are not in use, the most recently returned last
+    idle: Vec<(Connection, Instant)>,
+    /// connections of the pool, idle, in use or being opened
+    size: usize,
+    /// tasks waiting for a connection
+    waiters: Waiters,
+}
+
+impl ConnectionPool {
+    /// pool of connections opened with the settings of the builder, nothing is opened until `get`
+    /// defaults to at most 4 connections, a 60 second idle timeout,
+    /// and a health check of connections that were idle for more than 10 seconds
+    #[must_use]
+    pub fn new(builder: ClientBuilder) -> ConnectionPool {
+        ConnectionPool {
+            builder: Arc::new(builder),
+            max_size: DEFAULT_MAX_SIZE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            check_after: DEFAULT_CHECK_AFTER,
+            shared: Arc::default(),
+        }
+    }
+
+    /// most connections the pool opens, at least one
+    #[must_use]
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    /// idle connections are closed after this time, servers drop idle clients too
+    #[must_use]
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// send `ver` to connections that were idle for longer than this before handing them out,
+    /// `Duration::ZERO` checks every time
+    #[must_use]
+    pub fn health_check_after(mut self, idle: Duration) -> Self {
+        self.check_after = idle;
+        self
+    }
+
+    /// number of idle connections in the pool
+    #[must_use]
+    pub fn idle_connections(&self) -> usize {
+        self.state().idle.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// take a connection from the pool, opening a new one when none is idle
+    /// waits when the pool has `max_size` connections in use
+    pub async fn get(&self) -> Result<PooledConnection, GnuDbError> {
+        loop {
+            let slot = self.acquire().await;
+            // from here on dropping the handle returns the slot, even when this future is dropped
+            let mut pooled = PooledConnection {
+                con: None,
+                pool: self.clone(),
+            };
+            match slot {
+                Some((con, since)) => {
+                    let con = pooled.con.insert(con);
+                    if since.elapsed() <= self.check_after {
+                        return Ok(pooled);
+                    }
+                    match con.ver().await {
+                        Ok(_) if !con.is_closed() => return Ok(pooled),
+                        Ok(_) => debug!("idle connection was closed by the server"),
+                        Err(e) => debug!("idle connection failed the health check: {e}"),
+                    }
+                    con.close();
+                }
+                None => {
+                    // when the connection fails, dropping the empty handle frees its slot
+                    pooled.con = Some(self.builder.connect().await?);
+                    return Ok(pooled);
+                }
+            }
+        }
+    }
+
+    /// wait for an idle connection, and the time it was returned,
+    /// or for room to open a new one (None)
+    async fn acquire(&self) -> Option<(Connection, Instant)> {
+        waiters::wait_for(
+            &self.shared,
+            |state: &mut State| &mut state.waiters,
+            |state| {
+                state.evict_expired(self.idle_timeout);
+                if let Some(idle) = state.idle.pop() {
+                    return Some(Some(idle));
+                }
+                if state.size < self.max_size {
+                    state.size += 1;
+                    return Some(None);
+                }
+                None
+            },
+        )
+        .await
+    }
+
+    /// put a connection back, or free its slot when it cannot be used anymore
+    fn release(&self, con: Option<Connection>) {
+        let mut state = self.state();
+        match con {
+            Some(con) if !con.is_closed() => state.idle.push((con, Instant::now())),
+            _ => state.size -= 1,
+        }
+        // one connection or slot was freed, for one waiter
+        state.waiters.wake_one();
+    }
+
+    /// query the server for a given discid on a connection of the pool
+    pub async fn query(&self, discid: &DiscId) -> Result<Vec<Match>, GnuDbError> {
+        let cmd = parser::create_query_cmd(discid)?;
+        cddbp::cddb_query(&mut *self.get().await?, cmd).await
+    }
+
+    /// read all data of a given disc on a connection of the pool
+    pub async fn read(&self, single_match: &Match) -> Result<Disc, GnuDbError> {
+        self.get().await?.read(single_match).await
+    }
+}
+
+impl State {
+    /// close the connections that were idle for too long
+    fn evict_expired(&mut self, idle_timeout: Duration) {
+        let before = self.idle.len();
+        self.idle
+            .retain(|(_, since)| since.elapsed() < idle_timeout);
+        let expired = before - self.idle.len();
+        if expired > 0 {
+            debug!("closed {expired} idle connections");
+        }
+        self.size -= expired;
+    }
+}
+
+/// A connection taken from a `ConnectionPool`, it has all methods of `Connection`
+/// and goes back to the pool when dropped
+pub struct PooledConnection {
+    /// None while the connection is being opened
+    con: Option<Connection>,
+    pool: ConnectionPool,
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.con.as_ref().expect("pooled connection is open")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.con.as_mut().expect("pooled connection is open")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        self.pool.release(self.con.take());
+    }
+}
+
+impl CddbClient for ConnectionPool {
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+        let cmd = parser::create_query_cmd(discid);
+        Box::pin(async move { cddbp::cddb_query(&mut *self.get().await?, cmd?).await })
+    }
+
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+        Box::pin(ConnectionPool::read(self, single_match))
+    }
+
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        Box::pin(async move { cddbp::cddb_read_raw(&mut *self.get().await?, single_match).await })
+    }
+
+    fn sites(&mut self) -> BoxFuture<'_, Result<Vec<Site>, GnuDbError>> {
+        Box::pin(async move { self.get().await?.sites().await })
+    }
+
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        Box::pin(async move { self.get().await?.lscat().await })
+    }
+}
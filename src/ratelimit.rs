@@ -0,0 +1,265 @@
+//! Client side rate limiting, see `ClientBuilder::rate_limiter`
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use futures_lite::FutureExt;
+
+use crate::error::GnuDbError;
+use crate::runtime;
+use crate::waiters::{self, Waiters};
+
+const CONNECTION_TIMEOUT: &str = "timed out waiting for a free connection to the host";
+
+/// Limits the requests sent to the servers and the connections kept open to each of them,
+/// so batch jobs stay within the per-IP limits of gnudb
+///
+/// Clones share their state: give the same limiter to every builder, client and connection
+/// of the application and they are limited together.
+/// Every command sent over CDDBP and every HTTP request counts as a request: the hello and proto
+/// of the CDDBP login, quit, the extra requests of a protocol level fallback and retries included.
+/// A CDDBP connection occupies a connection of its host until it is closed,
+/// an HTTP request only while it runs. Waiting for a free connection gives up after the
+/// connect timeout of the builder.
+/// ```
+/// use std::time::Duration;
+/// use gnudb::{ClientBuilder, RateLimiter};
+///
+/// // at most 10 requests per minute, and a single connection per server
+/// let limiter = RateLimiter::new(10, Duration::from_secs(60)).max_connections_per_host(1);
+/// let cddbp = ClientBuilder::new().rate_limiter(limiter.clone());
+/// let http = ClientBuilder::new().rate_limiter(limiter).http().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    requests: usize,
+    interval: Duration,
+    max_connections: Option<usize>,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// signalled when a connection is released, for the blocking HTTP client
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// start times of the recent requests, including those reserved in the future
+    sent: VecDeque<Instant>,
+    /// open connections per host
+    connections: HashMap<String, usize>,
+    /// CDDBP connections waiting for a connection to be released, per host
+    /// the entries are kept when they are empty, the ids of their waiters stay unique
+    waiters: HashMap<String, Waiters>,
+}
+
+impl State {
+    /// count a new connection to the host, unless it already has `max` of them
+    fn open_connection(&mut self, host: &str, max: usize) -> bool {
+        if self.connections.get(host).copied().unwrap_or(0) >= max {
+            return false;
+        }
+        *self.connections.entry(host.to_owned()).or_default() += 1;
+        true
+    }
+}
+
+impl RateLimiter {
+    /// allow the given number of requests per interval, at least one
+    /// the number of connections per host is not limited unless `max_connections_per_host` is set
+    #[must_use]
+    pub fn new(requests: u32, interval: Duration) -> RateLimiter {
+        RateLimiter {
+            requests: usize::try_from(requests).unwrap_or(usize::MAX).max(1),
+            interval,
+            max_connections: None,
+            shared: Arc::default(),
+        }
+    }
+
+    /// allow at most the given number of connections to the same host at the same time, at least one
+    #[must_use]
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.max_connections = Some(max.max(1));
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// reserve a slot for a request, returns how long to wait before sending it
+    pub(crate) fn reserve_request(&self) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state();
+        while state
+            .sent
+            .front()
+            .is_some_and(|sent| *sent + self.interval <= now)
+        {
+            state.sent.pop_front();
+        }
+        let slot = if state.sent.len() < self.requests {
+            now
+        } else {
+            // the request that is `requests` requests back must have left the window
+            (state.sent[state.sent.len() - self.requests] + self.interval).max(now)
+        };
+        state.sent.push_back(slot);
+        slot.saturating_duration_since(now)
+    }
+
+    /// wait at most `timeout` for a free connection to the host, it is released when the permit
+    /// is dropped; dropping the future stops waiting
+    pub(crate) async fn acquire_connection(
+        &self,
+        host: &str,
+        timeout: Duration,
+    ) -> Result<ConnectionPermit, GnuDbError> {
+        if let Some(max) = self.max_connections {
+            let waiting = waiters::wait_for(
+                &self.shared.state,
+                |state: &mut State| state.waiters.entry(host.to_owned()).or_default(),
+                |state| state.open_connection(host, max).then_some(Ok(())),
+            );
+            waiting
+                .or(runtime::timeout(timeout, CONNECTION_TIMEOUT))
+                .await?;
+        }
+        Ok(self.permit(host))
+    }
+
+    /// `acquire_connection` for the blocking HTTP client, blocks the calling thread
+    pub(crate) fn acquire_connection_blocking(
+        &self,
+        host: &str,
+        timeout: Duration,
+    ) -> Result<ConnectionPermit, GnuDbError> {
+        if let Some(max) = self.max_connections {
+            let deadline = Instant::now() + timeout;
+            let mut state = self.state();
+            while !state.open_connection(host, max) {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(GnuDbError::Timeout(CONNECTION_TIMEOUT.to_owned()));
+                }
+                state = self
+                    .shared
+                    .released
+                    .wait_timeout(state, left)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+        Ok(self.permit(host))
+    }
+
+    fn permit(&self, host: &str) -> ConnectionPermit {
+        ConnectionPermit {
+            limiter: self.clone(),
+            host: host.to_owned(),
+        }
+    }
+}
+
+/// a connection to a host counted by a `RateLimiter`
+#[derive(Debug)]
+pub(crate) struct ConnectionPermit {
+    limiter: RateLimiter,
+    host: String,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if self.limiter.max_connections.is_none() {
+            return;
+        }
+        let mut state = self.limiter.state();
+        if let Some(count) = state.connections.get_mut(&self.host) {
+            *count -= 1;
+            if *count == 0 {
+                state.connections.remove(&self.host);
+            }
+        }
+        if let Some(waiters) = state.waiters.get_mut(&self.host) {
+            waiters.wake_one();
+        }
+        self.limiter.shared.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Executor;
+    use std::thread;
+
+    #[test]
+    fn test_reserve_request() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        assert_eq!(limiter.reserve_request(), Duration::ZERO);
+        assert_eq!(limiter.clone().reserve_request(), Duration::ZERO);
+        let wait = limiter.reserve_request();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        let wait = limiter.reserve_request();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        // the fifth request waits for the third to leave the window
+        let wait = limiter.reserve_request();
+        assert!(wait > Duration::from_secs(19) && wait <= Duration::from_secs(20));
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_connections_per_host() -> Result<(), GnuDbError> {
+        let limiter = RateLimiter::new(100, Duration::from_secs(1)).max_connections_per_host(1);
+        let permit = limiter.acquire_connection_blocking("gnudb.gnudb.org", TIMEOUT)?;
+        // other hosts are not affected
+        drop(limiter.acquire_connection_blocking("freedb.example.com", TIMEOUT)?);
+
+        let waiting = limiter.clone();
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            let _permit = waiting.acquire_connection_blocking("gnudb.gnudb.org", TIMEOUT);
+            start.elapsed()
+        });
+        thread::sleep(Duration::from_millis(100));
+        drop(permit);
+        assert!(handle.join().unwrap() >= Duration::from_millis(100));
+        assert!(limiter.state().connections.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_wait_times_out() -> Result<(), GnuDbError> {
+        let limiter = RateLimiter::new(100, Duration::from_secs(1)).max_connections_per_host(1);
+        let executor = Executor::new()?;
+        let permit = executor.block_on(limiter.acquire_connection("gnudb.gnudb.org", TIMEOUT))?;
+        let short = Duration::from_millis(50);
+        let waiting = executor.block_on(limiter.acquire_connection("gnudb.gnudb.org", short));
+        assert!(matches!(waiting, Err(GnuDbError::Timeout(_))));
+        // and it left the waiters
+        assert!(limiter.state().waiters["gnudb.gnudb.org"].is_empty());
+        let waiting = limiter.acquire_connection_blocking("gnudb.gnudb.org", short);
+        assert!(matches!(waiting, Err(GnuDbError::Timeout(_))));
+
+        // the waiter that gave up holds no connection, the next one gets in once the first is released
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(permit);
+        });
+        drop(executor.block_on(limiter.acquire_connection("gnudb.gnudb.org", TIMEOUT))?);
+        releaser.join().unwrap();
+        assert!(limiter.state().connections.is_empty());
+        Ok(())
+    }
+}
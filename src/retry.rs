@@ -0,0 +1,204 @@
+//! Retry of requests that failed for a transient reason, see `ClientBuilder::retry`
+
+use std::{
+    hash::{BuildHasher, Hasher, RandomState},
+    time::Duration,
+};
+
+use crate::error::{Command, GnuDbError};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEFAULT_MULTIPLIER: u32 = 2;
+
+/// How often and how fast failed requests are sent again
+///
+/// After a failed attempt the client waits for the backoff, which starts at the initial backoff
+/// and is multiplied after every attempt up to the maximum. With jitter each wait is a random
+/// duration between half and all of the backoff, so many clients do not retry in lockstep.
+/// Only errors accepted by the predicate are retried, by default those of `GnuDbError::is_retryable`:
+/// busy servers, timeouts and broken connections.
+/// A broken CDDBP connection is opened again and logged in before the command is replayed.
+/// ```
+/// use std::time::Duration;
+/// use gnudb::{ClientBuilder, RetryPolicy, error::GnuDbError};
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .initial_backoff(Duration::from_millis(200))
+///     // do not hammer a server that limits our access
+///     .retry_if(|e| e.is_retryable() && !matches!(e, GnuDbError::LimitExceeded { .. }));
+/// let builder = ClientBuilder::new().retry(policy);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: u32,
+    jitter: bool,
+    retryable: fn(&GnuDbError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            multiplier: DEFAULT_MULTIPLIER,
+            jitter: true,
+            retryable: GnuDbError::is_retryable,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 3 attempts, with a backoff of 0.5 seconds that doubles up to 30 seconds, with jitter
+    #[must_use]
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// a single attempt, the policy of a `ClientBuilder` unless `retry` is called
+    #[must_use]
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// number of times a request is sent, including the first one, at least 1
+    #[must_use]
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// wait before the second attempt
+    #[must_use]
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// longest wait between two attempts
+    #[must_use]
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// factor the backoff grows with after every attempt
+    #[must_use]
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// randomize the waits, on by default
+    #[must_use]
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// decide which errors are retried, replacing `GnuDbError::is_retryable`
+    #[must_use]
+    pub fn retry_if(mut self, retryable: fn(&GnuDbError) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub(crate) fn max_attempt_count(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub(crate) fn is_retryable(&self, err: &GnuDbError) -> bool {
+        (self.retryable)(err)
+    }
+
+    /// true when the given attempt (counting from 1) failed with an error worth another attempt
+    pub(crate) fn should_retry(&self, attempt: u32, err: &GnuDbError) -> bool {
+        attempt < self.max_attempts && self.is_retryable(err)
+    }
+
+    /// wait after the given failed attempt, counting from 1
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        if self.jitter {
+            backoff.mul_f64(0.5 + random_fraction() / 2.0)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// random number in [0, 1), the hasher keys are seeded randomly for every `RandomState`
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    #[allow(clippy::cast_precision_loss)]
+    let fraction = bits as f64 / (1u64 << 53) as f64;
+    fraction
+}
+
+/// the error of a reply to the given command whose status line reports a failure,
+/// like "433 No connections allowed"
+pub(crate) fn failed_reply(raw: &str, command: Command) -> Option<GnuDbError> {
+    let status = raw.lines().next()?;
+    (status.starts_with('4') || status.starts_with('5'))
+        .then(|| GnuDbError::from_status(status, command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(500))
+            .jitter(false);
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+
+        let policy = policy.jitter(true);
+        for attempt in 1..5 {
+            let backoff = policy.backoff(attempt);
+            let limit = policy.jitter(false).backoff(attempt);
+            assert!(backoff >= limit / 2 && backoff <= limit, "{backoff:?}");
+        }
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let busy = GnuDbError::from_status("433 No connections allowed: 10 users", Command::Other);
+        let policy = RetryPolicy::new();
+        assert!(policy.should_retry(1, &busy));
+        assert!(policy.should_retry(2, &busy));
+        assert!(!policy.should_retry(3, &busy));
+        assert!(!policy.should_retry(1, &GnuDbError::InvalidCategory("pop".to_owned())));
+        assert!(!RetryPolicy::never().should_retry(1, &busy));
+        assert!(
+            !RetryPolicy::new()
+                .retry_if(|_| false)
+                .should_retry(1, &busy)
+        );
+    }
+
+    #[test]
+    fn test_failed_reply() {
+        assert!(failed_reply("200 rock 6909aa09 A / B\r\n", Command::Other).is_none());
+        assert!(matches!(
+            failed_reply("417 Access limit exceeded\r\n", Command::Other),
+            Some(GnuDbError::LimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! The parts of the async runtime the crate depends on: TCP connections, timers,
+//! the blocking thread pool and a single threaded executor
+//! `runtime-smol` (the default) and `runtime-tokio` select the runtime, smol wins when both are enabled
+
+use std::{io, time::Duration};
+
+use futures_lite::io::{AsyncRead, AsyncWrite};
+
+#[cfg(not(any(feature = "runtime-smol", feature = "runtime-tokio")))]
+compile_error!("enable one of the features runtime-smol or runtime-tokio");
+
+/// byte stream a CDDBP connection runs over
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// complete with a `TimedOut` error after the given duration
+pub(crate) async fn timeout<T>(duration: Duration, message: &str) -> io::Result<T> {
+    imp::sleep(duration).await;
+    Err(io::Error::new(io::ErrorKind::TimedOut, message))
+}
+
+#[cfg(any(feature = "blocking", test))]
+pub(crate) use imp::Executor;
+pub(crate) use imp::{connect_tcp, sleep, unblock};
+
+#[cfg(feature = "runtime-smol")]
+mod imp {
+    use super::{Stream, io};
+    use std::time::Duration;
+
+    #[cfg(any(feature = "blocking", test))]
+    use smol::LocalExecutor;
+    use smol::{Timer, net::TcpStream};
+
+    pub(crate) async fn connect_tcp(addr: &str) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::connect(addr).await?))
+    }
+
+    pub(crate) async fn sleep(duration: Duration) {
+        Timer::after(duration).await;
+    }
+
+    /// run blocking code on the blocking thread pool
+    pub(crate) async fn unblock<T, F>(f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        smol::unblock(f).await
+    }
+
+    /// single threaded executor to drive futures from synchronous code
+    #[cfg(any(feature = "blocking", test))]
+    pub(crate) struct Executor(LocalExecutor<'static>);
+
+    #[cfg(any(feature = "blocking", test))]
+    impl Executor {
+        pub(crate) fn new() -> io::Result<Executor> {
+            Ok(Executor(LocalExecutor::new()))
+        }
+
+        pub(crate) fn block_on<T>(&self, future: impl std::future::Future<Output = T>) -> T {
+            smol::block_on(self.0.run(future))
+        }
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-smol")))]
+mod imp {
+    use super::{Stream, io};
+    use std::time::Duration;
+
+    use tokio::net::TcpStream;
+    #[cfg(any(feature = "blocking", test))]
+    use tokio::runtime::Runtime;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    pub(crate) async fn connect_tcp(addr: &str) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::connect(addr).await?.compat()))
+    }
+
+    pub(crate) async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// run blocking code on the blocking thread pool of the current runtime
+    pub(crate) async fn unblock<T, F>(f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(value) => value,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// single threaded runtime to drive futures from synchronous code
+    #[cfg(any(feature = "blocking", test))]
+    pub(crate) struct Executor(Runtime);
+
+    #[cfg(any(feature = "blocking", test))]
+    impl Executor {
+        pub(crate) fn new() -> io::Result<Executor> {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map(Executor)
+        }
+
+        pub(crate) fn block_on<T>(&self, future: impl std::future::Future<Output = T>) -> T {
+            self.0.block_on(future)
+        }
+    }
+}
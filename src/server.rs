@@ -0,0 +1,167 @@
+use log::debug;
+
+use crate::{Category, client::DEFAULT_CGI_PATH, error::GnuDbError};
+
+/// A server listed by the `sites` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    pub host: String,
+    /// "cddbp" or "http"
+    pub protocol: String,
+    pub port: u16,
+    /// CGI path for HTTP sites, "-" for CDDBP sites
+    pub address: String,
+    /// degrees, positive is north
+    pub latitude: f64,
+    /// degrees, positive is east
+    pub longitude: f64,
+    pub description: String,
+}
+
+impl Site {
+    /// a CDDBP site, for building a mirror list by hand
+    #[must_use]
+    pub fn cddbp(host: &str, port: u16) -> Site {
+        Site {
+            host: host.to_owned(),
+            protocol: "cddbp".to_owned(),
+            port,
+            address: "-".to_owned(),
+            latitude: 0.0,
+            longitude: 0.0,
+            description: String::new(),
+        }
+    }
+
+    /// an HTTP site with the default CGI path, for building a mirror list by hand
+    #[must_use]
+    pub fn http(host: &str, port: u16) -> Site {
+        Site {
+            protocol: "http".to_owned(),
+            address: DEFAULT_CGI_PATH.to_owned(),
+            ..Site::cddbp(host, port)
+        }
+    }
+}
+
+/// Message of the day, as returned by the `motd` command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Motd {
+    pub last_modified: Option<String>,
+    pub text: String,
+}
+
+/// Server version, as returned by the `ver` command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerVersion {
+    /// server software, like "cddbd"
+    pub name: String,
+    /// version of the server software, like "v1.5.2PL0"
+    pub version: String,
+    /// the complete version message
+    pub text: String,
+}
+
+/// Server status, as returned by the `stat` command
+/// the flags mirror the yes/no lines of the server output
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub current_proto: u32,
+    pub max_proto: u32,
+    /// "cddbp" or "http"
+    pub interface: String,
+    pub gets: bool,
+    pub puts: bool,
+    pub updates: bool,
+    pub posting: bool,
+    pub validation: String,
+    pub quotes: bool,
+    pub strip_ext: bool,
+    pub secure: bool,
+    pub current_users: u32,
+    pub max_users: u32,
+    pub database_entries: u64,
+    pub entries_by_category: Vec<(Category, u64)>,
+}
+
+/// run `f` against the sites of the given protocol in order, until one of them can serve it
+/// only unreachable or busy servers make it move on to the next site
+pub(crate) fn fail_over<T>(
+    sites: &[Site],
+    protocol: &str,
+    mut f: impl FnMut(&Site) -> Result<T, GnuDbError>,
+) -> Result<T, GnuDbError> {
+    let mut fail_over = FailOver::new(protocol);
+    for site in fail_over.sites(sites) {
+        match f(site) {
+            Ok(value) => return Ok(value),
+            Err(e) => fail_over.skip(site, e)?,
+        }
+    }
+    Err(fail_over.into_error())
+}
+
+/// the sites of a mirror list, starting at index `first` and wrapping around
+pub(crate) fn rotated(sites: &[Site], first: usize) -> Vec<Site> {
+    let first = first.min(sites.len());
+    sites[first..]
+        .iter()
+        .chain(&sites[..first])
+        .cloned()
+        .collect()
+}
+
+/// index of the site of the protocol on `host` and `port` in a mirror list
+pub(crate) fn position(sites: &[Site], protocol: &str, host: &str, port: u16) -> Option<usize> {
+    sites
+        .iter()
+        .position(|site| site.protocol == protocol && site.host == host && site.port == port)
+}
+
+/// bookkeeping of a fail over across the sites of one protocol, for callers that cannot
+/// use `fail_over`, like the async CDDBP connect
+pub(crate) struct FailOver<'a> {
+    protocol: &'a str,
+    last_err: Option<GnuDbError>,
+}
+
+impl<'a> FailOver<'a> {
+    pub(crate) fn new(protocol: &'a str) -> Self {
+        FailOver {
+            protocol,
+            last_err: None,
+        }
+    }
+
+    /// the sites to try, in order
+    pub(crate) fn sites<'s>(
+        &self,
+        sites: &'s [Site],
+    ) -> impl Iterator<Item = &'s Site> + use<'a, 's> {
+        let protocol = self.protocol;
+        sites.iter().filter(move |site| site.protocol == protocol)
+    }
+
+    /// move on after `site` failed: Ok when the next site may be tried,
+    /// the error itself when the server was reachable and did not report itself busy
+    pub(crate) fn skip(&mut self, site: &Site, e: GnuDbError) -> Result<(), GnuDbError> {
+        if !e.is_server_unavailable() {
+            return Err(e);
+        }
+        debug!(
+            "{}:{} unavailable, trying next site: {e}",
+            site.host, site.port
+        );
+        self.last_err = Some(e);
+        Ok(())
+    }
+
+    /// the error to report once every site failed
+    pub(crate) fn into_error(self) -> GnuDbError {
+        self.last_err.unwrap_or(GnuDbError::ConnectionError(format!(
+            "no {} site available",
+            self.protocol
+        )))
+    }
+}
@@ -1,16 +1,43 @@
-// Network/integration tests
-// These use #[serial] because gnudb doesn't like multiple connections from same IP
-// These tests are ignored by default to avoid network calls on CI
+// Integration tests
+// Most run the clients against local stand-in HTTP and CDDBP servers started on 127.0.0.1
+// The few that talk to gnudb.gnudb.org are ignored by default to avoid network calls on CI,
+// and use #[serial] because gnudb doesn't like multiple connections from same IP
+// The fixtures shared with the unit tests of the other modules live here as well
+
+use std::{
+    fs, io,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
 use discid::DiscId;
+use futures_lite::{
+    FutureExt,
+    io::{AsyncRead, AsyncWrite},
+};
 use log::debug;
 use serial_test::serial;
 
-use crate::{Connection, http_query, http_read};
+use crate::{
+    AsyncHttpClient, Category, CddbClient, ClientBuilder, ClientConfig, Connection, ConnectionPool,
+    Disc, LocalDb, Match, ParseOptions, RateLimiter, RetryPolicy, Site, Submission, SubmitMode,
+    Track, Transport, error::GnuDbError, http_query, http_read, http_submit,
+};
+
+thread_local! {
+    // one executor per test thread, tokio connections only work on the runtime that opened them
+    static EXECUTOR: crate::runtime::Executor = crate::runtime::Executor::new().unwrap();
+}
 
 macro_rules! aw {
     ($e:expr) => {
-        smol::block_on($e)
+        EXECUTOR.with(|executor| executor.block_on($e))
     };
 }
 
@@ -18,6 +45,516 @@ fn init_logger() {
     let _ = env_logger::builder().is_test(true).try_init();
 }
 
+/// local stand-in HTTP server: accepts a single request, answers with the given CDDB body
+/// and hands back the raw request (head and body) it received
+fn serve_once(cddb_body: &'static str) -> (u16, JoinHandle<String>) {
+    let (port, handle) = serve_http(vec![cddb_body]);
+    (
+        port,
+        thread::spawn(move || handle.join().unwrap().remove(0)),
+    )
+}
+
+/// local stand-in HTTP server: accepts one request per CDDB body, answers them in order
+/// and hands back the raw requests it received
+fn serve_http(cddb_bodies: Vec<&'static str>) -> (u16, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        cddb_bodies
+            .into_iter()
+            .map(|cddb_body| {
+                let (stream, _) = listener.accept().unwrap();
+                answer_http(stream, cddb_body)
+            })
+            .collect()
+    });
+    (port, handle)
+}
+
+fn answer_http(stream: TcpStream, cddb_body: &str) -> String {
+    let mut reader = BufReader::new(stream);
+    answer_http_request(&mut reader, cddb_body, true)
+}
+
+/// local stand-in HTTP server: accepts a single connection and answers all CDDB bodies on it,
+/// so it only works for clients that keep the connection alive
+fn serve_keep_alive(cddb_bodies: Vec<&'static str>) -> (u16, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let count = cddb_bodies.len();
+        cddb_bodies
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cddb_body)| answer_http_request(&mut reader, cddb_body, idx + 1 == count))
+            .collect()
+    });
+    (port, handle)
+}
+
+fn answer_http_request<S: Read + Write>(
+    reader: &mut BufReader<S>,
+    cddb_body: &str,
+    close: bool,
+) -> String {
+    let mut request = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().unwrap();
+        }
+        request.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    request.push_str(&String::from_utf8(body).unwrap());
+    let connection = if close { "close" } else { "keep-alive" };
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: {connection}\r\n\r\n{cddb_body}",
+        cddb_body.len()
+    );
+    reader.get_mut().write_all(response.as_bytes()).unwrap();
+    request
+}
+
+/// local stand-in HTTPS server with a certificate for localhost signed by testdata/ca.pem:
+/// accepts a single request and answers with the given CDDB body
+/// hands back the raw request, or None when the client gave up during the TLS handshake
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+fn serve_https_once(cddb_body: &'static str) -> (u16, JoinHandle<Option<String>>) {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+    let certs = CertificateDer::pem_slice_iter(include_bytes!("../testdata/server.pem"))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_slice(include_bytes!("../testdata/server.key")).unwrap();
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let conn = rustls::ServerConnection::new(Arc::new(config)).unwrap();
+        let mut tls = rustls::StreamOwned::new(conn, stream);
+        tls.conn.complete_io(&mut tls.sock).ok()?;
+        let mut reader = BufReader::new(tls);
+        Some(answer_http_request(&mut reader, cddb_body, true))
+    });
+    (port, handle)
+}
+
+/// local stand-in HTTP proxy: accepts a single CONNECT tunnel, then answers one request
+/// through it with the given CDDB body
+/// hands back the CONNECT request head and the tunnelled request
+fn serve_proxy(cddb_body: &'static str) -> (u16, JoinHandle<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            head.push_str(&line);
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .unwrap();
+        let request = answer_http_request(&mut reader, cddb_body, true);
+        (head, request)
+    });
+    (port, handle)
+}
+
+/// greeting of a stand-in CDDBP server that lets the client in
+pub(crate) const READY: &str = "201 localhost CDDBP server v1.5.2PL0 ready\r\n";
+
+/// a scripted CDDBP session after the greeting: the commands the server expects, by prefix and
+/// in order, each with its reply
+pub(crate) type Script = &'static [(&'static str, &'static str)];
+
+/// the reply to a command, consuming the script; hello and proto are accepted when they are not
+/// the next entry, any other unexpected command gets None and ends the session like a server
+/// dropping the connection
+fn script_reply(script: &mut Script, cmd: &str) -> Option<&'static str> {
+    match script.split_first() {
+        Some(((prefix, reply), rest)) if cmd.starts_with(prefix) => {
+            *script = rest;
+            Some(reply)
+        }
+        _ if cmd.starts_with("cddb hello") => Some("200 Hello and welcome\r\n"),
+        _ if cmd.starts_with("proto") => Some("201 OK, CDDB protocol level now: 6\r\n"),
+        _ => None,
+    }
+}
+
+/// local stand-in CDDBP server for several connections in a row, each session is a greeting
+/// and a script; a session that refuses the client ends after the greeting
+/// hands back the commands of all sessions, including the one that ended a session
+fn serve_cddbp(sessions: &'static [(&'static str, Script)]) -> (u16, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let mut commands = Vec::new();
+        for &(greeting, mut script) in sessions {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            reader.get_mut().write_all(greeting.as_bytes()).unwrap();
+            if !greeting.starts_with('2') {
+                continue;
+            }
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let cmd = line.trim_end().to_owned();
+                let reply = script_reply(&mut script, &cmd);
+                commands.push(cmd);
+                match reply {
+                    Some(reply) => reader.get_mut().write_all(reply.as_bytes()).unwrap(),
+                    None => break,
+                }
+            }
+        }
+        commands
+    });
+    (port, handle)
+}
+
+/// in-memory stand-in CDDBP server for `Connection::from_stream`, playing one scripted session
+/// without a socket; the commands it received are shared with the test
+pub(crate) struct ScriptedStream {
+    script: Option<Script>,
+    output: Vec<u8>,
+    line: Vec<u8>,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptedStream {
+    pub(crate) fn new(greeting: &str, script: Script) -> (ScriptedStream, Arc<Mutex<Vec<String>>>) {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let stream = ScriptedStream {
+            script: Some(script),
+            output: greeting.as_bytes().to_vec(),
+            line: Vec::new(),
+            commands: Arc::clone(&commands),
+        };
+        (stream, commands)
+    }
+}
+
+impl AsyncRead for ScriptedStream {
+    /// the pending replies, end of stream once they are read like a server waiting for a command
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = buf.len().min(self.output.len());
+        buf[..n].copy_from_slice(&self.output[..n]);
+        self.output.drain(..n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for ScriptedStream {
+    /// every complete command line queues its reply, nothing is answered after a hang up
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        this.line.extend_from_slice(buf);
+        while let Some(end) = this.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = this.line.drain(..=end).collect();
+            let cmd = String::from_utf8_lossy(&line).trim_end().to_owned();
+            if let Some(script) = &mut this.script {
+                match script_reply(script, &cmd) {
+                    Some(reply) => this.output.extend_from_slice(reply.as_bytes()),
+                    None => this.script = None,
+                }
+                this.commands.lock().unwrap().push(cmd);
+            }
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// a port nobody listens on
+fn closed_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// a fresh directory in the system temp dir, unique to the test process
+pub(crate) fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("gnudb-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    root
+}
+
+/// the first tracks of the Dire Straits debut, without TOC
+pub(crate) fn sample_disc() -> Disc {
+    Disc {
+        title: "Dire Straits".to_owned(),
+        artist: "DIRE STRAITS".to_owned(),
+        year: Some(1978),
+        genre: Some("Rock".to_owned()),
+        tracks: ["Down to the waterline", "Water of love"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| Track {
+                number: u32::try_from(i).unwrap() + 1,
+                title: (*title).to_owned(),
+                artist: "DIRE STRAITS".to_owned(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// the match of `sample_disc` in the rock category
+pub(crate) fn sample_match() -> Match {
+    Match {
+        discid: "6909aa09".to_owned(),
+        category: Category::Rock,
+        artist: "DIRE STRAITS".to_owned(),
+        title: "Dire Straits".to_owned(),
+    }
+}
+
+#[test]
+fn test_http_submit_local() {
+    init_logger();
+    let (port, server) = serve_once("200 OK, submission is OK.\n");
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let disc = sample_disc();
+    let submission = Submission {
+        category: Category::Rock,
+        discid: &discid,
+        disc: &disc,
+        email: "me@example.com",
+        mode: SubmitMode::Test,
+    };
+    let response = http_submit("127.0.0.1", port, &submission).unwrap();
+    assert_eq!(response.code, 200);
+    assert_eq!(response.message, "OK, submission is OK.");
+
+    let request = server.join().unwrap();
+    let lower = request.to_lowercase();
+    assert!(request.starts_with("POST /~cddb/submit.cgi HTTP/1.1\r\n"));
+    assert!(lower.contains("category: rock\r\n"));
+    assert!(lower.contains(&format!("discid: {}\r\n", discid.freedb_id())));
+    assert!(lower.contains("user-email: me@example.com\r\n"));
+    assert!(lower.contains("submit-mode: test\r\n"));
+    assert!(lower.contains("charset: utf-8\r\n"));
+    assert!(request.contains("\r\n\r\n# xmcd\n"));
+    assert!(request.contains("DTITLE=DIRE STRAITS / Dire Straits\n"));
+    assert!(request.contains("TTITLE1=Water of love\n"));
+    assert!(request.contains("#\t150\n#\t18051\n"));
+    assert!(request.contains("# Disc length: 563 seconds\n"));
+}
+
+#[test]
+fn test_http_submit_rejected_local() {
+    init_logger();
+    let (port, server) = serve_once("500 Invalid header information.\n");
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let disc = sample_disc();
+    let submission = Submission {
+        category: Category::Rock,
+        discid: &discid,
+        disc: &disc,
+        email: "me@example.com",
+        mode: SubmitMode::Submit,
+    };
+    let err = http_submit("127.0.0.1", port, &submission).unwrap_err();
+    match err {
+        GnuDbError::SubmitRejected { code, message } => {
+            assert_eq!(code, 500);
+            assert_eq!(message, "Invalid header information.");
+        }
+        other => panic!("unexpected error type: {other:?}"),
+    }
+    let request = server.join().unwrap();
+    assert!(request.to_lowercase().contains("submit-mode: submit\r\n"));
+}
+
+#[test]
+fn test_set_toc() {
+    let discid = DiscId::put(1, &[42_248, 150, 18_051, 30_000]).unwrap();
+    let mut disc = sample_disc();
+    disc.set_toc(&discid);
+    assert_eq!(disc.discids, vec![discid.freedb_id()]);
+    assert_eq!(disc.length, 563);
+    assert_eq!(disc.tracks.len(), 3);
+    assert_eq!(disc.tracks[1].title, "Water of love");
+    assert_eq!(disc.tracks[2].title, "");
+    assert_eq!(disc.tracks[2].number, 3);
+    let offsets: Vec<u32> = disc.tracks.iter().map(|t| t.offset).collect();
+    assert_eq!(offsets, vec![150, 18_051, 30_000]);
+    assert_eq!(disc.tracks[2].duration, 163);
+    // a correction keeps the other discids of the record
+    disc.discids.push("6909aa0a".to_owned());
+    disc.set_toc(&discid);
+    assert_eq!(
+        disc.discids,
+        vec![discid.freedb_id(), "6909aa0a".to_owned()]
+    );
+}
+
+#[test]
+fn test_matches_toc() {
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let mut disc = sample_disc();
+    assert!(!disc.matches_toc(&discid));
+    disc.set_toc(&discid);
+    assert!(disc.matches_toc(&discid));
+    disc.tracks[1].offset += 1;
+    assert!(!disc.matches_toc(&discid));
+    disc.tracks[1].offset -= 1;
+    disc.length += 1;
+    assert!(!disc.matches_toc(&discid));
+    let other = DiscId::put(1, &[42_248, 150, 18_052]).unwrap();
+    disc.length -= 1;
+    assert!(!disc.matches_toc(&other));
+}
+
+#[test]
+fn test_bump_revision() {
+    let mut disc = sample_disc();
+    disc.revision = 7;
+    disc.bump_revision();
+    assert_eq!(disc.revision, 8);
+}
+
+#[test]
+fn test_http_submit_unknown_category() {
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let disc = sample_disc();
+    let submission = Submission {
+        category: Category::Unknown("electronic".to_owned()),
+        discid: &discid,
+        disc: &disc,
+        email: "me@example.com",
+        mode: SubmitMode::Test,
+    };
+    let err = http_submit("127.0.0.1", 9, &submission).unwrap_err();
+    assert!(matches!(err, GnuDbError::InvalidCategory(_)));
+}
+
+#[test]
+fn test_cddbp_server_commands_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[
+            (
+                "sites",
+                "210 OK, site information follows (until terminating `.')\r\n\
+             gnudb.gnudb.org cddbp 8880 - N000.00 W000.00 GnuDB\r\n.\r\n",
+            ),
+            (
+                "motd",
+                "210 Last modified: 01/02/24 10:00:00 MOTD follows (until terminating `.')\r\n\
+             Be nice.\r\n.\r\n",
+            ),
+            (
+                "stat",
+                "210 OK, status information follows (until terminating `.')\r\n\
+             Server status:\r\n    current proto: 6\r\n    max users: 100\r\n\
+             Database entries: 2\r\n.\r\n",
+            ),
+            (
+                "ver",
+                "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\r\n",
+            ),
+            ("whom", "401 No user information available.\r\n"),
+            (
+                "help",
+                "210 OK, help information follows (until terminating `.')\r\n\
+             The following commands are supported:\r\n.\r\n",
+            ),
+            (
+                "cddb lscat",
+                "210 OK, category list follows (until terminating `.')\r\nrock\r\njazz\r\n.\r\n",
+            ),
+            ("discid", "200 Disc ID is 6909aa09\r\n"),
+            ("quit", "230 localhost Closing connection.  Goodbye.\r\n"),
+        ],
+    )]);
+    let mut con = aw!(Connection::from_host_port("127.0.0.1", port)).unwrap();
+
+    let sites = aw!(con.sites()).unwrap();
+    assert_eq!(sites.len(), 1);
+    assert_eq!(sites[0].port, 8880);
+    let motd = aw!(con.motd()).unwrap();
+    assert_eq!(motd.last_modified.as_deref(), Some("01/02/24 10:00:00"));
+    assert_eq!(motd.text, "Be nice.\n");
+    let stats = aw!(con.stat()).unwrap();
+    assert_eq!(stats.current_proto, 6);
+    assert_eq!(stats.max_users, 100);
+    assert_eq!(stats.database_entries, 2);
+    let version = aw!(con.ver()).unwrap();
+    assert_eq!(version.version, "v1.5.2PL0");
+    assert!(aw!(con.whom()).is_err());
+    let help = aw!(con.help(Some("discid"))).unwrap();
+    assert_eq!(help, "The following commands are supported:\n");
+    let categories = aw!(con.lscat()).unwrap();
+    assert_eq!(categories, vec![Category::Rock, Category::Jazz]);
+    let discid = aw!(con.discid(&[150, 18_051], 2476)).unwrap();
+    assert_eq!(discid, "6909aa09");
+    aw!(con.quit()).unwrap();
+
+    let commands = server.join().unwrap();
+    assert_eq!(
+        commands[2..],
+        [
+            "sites",
+            "motd",
+            "stat",
+            "ver",
+            "whom",
+            "help discid",
+            "cddb lscat",
+            "discid 2 150 18051 2476",
+            "quit"
+        ]
+    );
+}
+
 #[test]
 #[serial]
 #[ignore = "requires network"]
@@ -109,3 +646,934 @@ fn test_inexact_search() {
     assert_eq!(disc.title, "Dire Straits");
     assert_eq!(disc.artist, "DIRE STRAITS");
 }
+
+#[test]
+fn test_cddbp_from_sites_local() {
+    init_logger();
+    let (busy, _) = serve_cddbp(&[(
+        "433 No connections allowed: X users allowed, Y currently active\r\n",
+        &[],
+    )]);
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[("quit", "230 localhost Closing connection.  Goodbye.\r\n")],
+    )]);
+    let sites = [
+        Site::cddbp("127.0.0.1", closed_port()),
+        Site::cddbp("127.0.0.1", busy),
+        // HTTP sites are skipped by a CDDBP connection
+        Site::http("127.0.0.1", closed_port()),
+        Site::cddbp("127.0.0.1", port),
+    ];
+    let mut con = aw!(ClientBuilder::new().connect_sites(&sites)).unwrap();
+    aw!(con.quit()).unwrap();
+    let commands = server.join().unwrap();
+    assert_eq!(commands.last().map(String::as_str), Some("quit"));
+}
+
+#[test]
+fn test_cddbp_from_sites_all_busy_local() {
+    init_logger();
+    let (busy, _) = serve_cddbp(&[("432 No connections allowed: permission denied\r\n", &[])]);
+    let sites = [Site::cddbp("127.0.0.1", busy)];
+    match aw!(ClientBuilder::new().connect_sites(&sites)) {
+        Err(GnuDbError::PermissionDenied { code, .. }) => assert_eq!(code, 432),
+        Err(other) => panic!("unexpected error type: {other:?}"),
+        Ok(_) => panic!("connected to a busy server"),
+    }
+    match aw!(ClientBuilder::new().connect_sites(&[])) {
+        Err(GnuDbError::ConnectionError(_)) => {}
+        Err(other) => panic!("unexpected error type: {other:?}"),
+        Ok(_) => panic!("connected without any site"),
+    }
+}
+
+#[test]
+fn test_cddbp_sites_fail_over_mid_session_local() {
+    init_logger();
+    let (busy, busy_server) = serve_cddbp(&[(
+        READY,
+        &[(
+            "cddb query",
+            "433 No connections allowed: X users allowed, Y currently active\r\n",
+        )],
+    )]);
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[
+            (
+                "cddb query",
+                "200 rock 6909aa09 DIRE STRAITS / Dire Straits\r\n",
+            ),
+            ("quit", "230 localhost Closing connection.  Goodbye.\r\n"),
+        ],
+    )]);
+    let sites = vec![
+        Site::cddbp("127.0.0.1", busy),
+        Site::cddbp("127.0.0.1", port),
+    ];
+    let mut con = aw!(ClientBuilder::new().sites(sites).connect()).unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = aw!(con.query(&discid)).unwrap();
+    assert_eq!(matches[0].title, "Dire Straits");
+    aw!(con.quit()).unwrap();
+    let busy_commands = busy_server.join().unwrap();
+    assert!(busy_commands.last().unwrap().starts_with("cddb query"));
+    let commands = server.join().unwrap();
+    assert!(commands.iter().any(|cmd| cmd.starts_with("cddb hello")));
+    assert!(commands[commands.len() - 2].starts_with("cddb query"));
+}
+
+#[test]
+fn test_http_client_sites_fail_over_local() {
+    init_logger();
+    let (busy, busy_server) =
+        serve_once("433 No connections allowed: X users allowed, Y currently active\n");
+    let (port, server) = serve_http(vec![
+        "200 rock 6909aa09 DIRE STRAITS / Dire Straits\n",
+        "202 No match found\n",
+    ]);
+    let client = ClientBuilder::new()
+        .sites(vec![
+            Site::http("127.0.0.1", busy),
+            Site::http("127.0.0.1", port),
+        ])
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert_eq!(client.query(&discid).unwrap().len(), 1);
+    // the next request starts at the site that answered
+    assert!(client.query(&discid).unwrap().is_empty());
+    busy_server.join().unwrap();
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+#[test]
+fn test_http_query_sites_local() {
+    init_logger();
+    let (limited, limited_server) = serve_once("417 Access limit exceeded\n");
+    let (port, server) = serve_once("200 rock 6909aa09 DIRE STRAITS / Dire Straits\n");
+    let mut mirror = Site::http("127.0.0.1", port);
+    mirror.address = "/cgi-bin/cddb.cgi".to_owned();
+    let sites = [
+        Site::http("127.0.0.1", limited),
+        Site::cddbp("127.0.0.1", closed_port()),
+        mirror,
+    ];
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = ClientBuilder::new()
+        .http_query_sites(&sites, &discid)
+        .unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].category, Category::Rock);
+    assert!(
+        limited_server
+            .join()
+            .unwrap()
+            .starts_with("GET /~cddb/cddb.cgi?")
+    );
+    assert!(server.join().unwrap().starts_with("GET /cgi-bin/cddb.cgi?"));
+}
+
+#[test]
+fn test_http_query_sites_stops_on_other_errors_local() {
+    init_logger();
+    let (port, server) = serve_once("500 Command syntax error\n");
+    let sites = [
+        Site::http("127.0.0.1", port),
+        Site::http("127.0.0.1", closed_port()),
+    ];
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    match ClientBuilder::new().http_query_sites(&sites, &discid) {
+        Err(GnuDbError::ServerError { code, .. }) => assert_eq!(code, 500),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http_read_sites_local() {
+    init_logger();
+    let (busy, busy_server) =
+        serve_once("433 No connections allowed: X users allowed, Y currently active\n");
+    let (port, server) = serve_once(
+        "210 rock 6909aa09 CD database entry follows (until terminating `.')\n\
+         DISCID=6909aa09\nDTITLE=DIRE STRAITS / Dire Straits\nTTITLE0=Down to the waterline\n.\n",
+    );
+    let sites = [Site::http("127.0.0.1", busy), Site::http("127.0.0.1", port)];
+    let disc = ClientBuilder::new()
+        .http_read_sites(&sites, &sample_match())
+        .unwrap();
+    assert_eq!(disc.title, "Dire Straits");
+    assert_eq!(disc.tracks[0].title, "Down to the waterline");
+    busy_server.join().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_sites_keep_builder_settings_local() {
+    init_logger();
+    let config = ClientConfig::new("joe", "my.host.com", "xmcd", "2.1").unwrap();
+    let builder = ClientBuilder::new()
+        .client_config(config)
+        .user_agent("myripper/1.0");
+
+    let (busy, _) = serve_cddbp(&[(
+        "433 No connections allowed: X users allowed, Y currently active\r\n",
+        &[],
+    )]);
+    let (port, server) = serve_cddbp(&[(READY, &[])]);
+    let sites = [
+        Site::cddbp("127.0.0.1", busy),
+        Site::cddbp("127.0.0.1", port),
+    ];
+    let con = aw!(builder.connect_sites(&sites)).unwrap();
+    drop(con);
+    let commands = server.join().unwrap();
+    assert_eq!(commands[0], "cddb hello joe my.host.com xmcd 2.1");
+
+    let (busy, busy_server) =
+        serve_once("433 No connections allowed: X users allowed, Y currently active\n");
+    let (port, server) = serve_once("202 No match found\n");
+    let sites = [Site::http("127.0.0.1", busy), Site::http("127.0.0.1", port)];
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = builder.http_query_sites(&sites, &discid).unwrap();
+    assert!(matches.is_empty());
+    busy_server.join().unwrap();
+    let request = server.join().unwrap();
+    assert!(request.contains("hello=joe%20my.host.com%20xmcd%202.1&"));
+    assert!(request.contains("user-agent: myripper/1.0\r\n"));
+}
+
+#[test]
+fn test_http_sites_local() {
+    init_logger();
+    let (port, server) = serve_once(
+        "210 OK, site information follows (until terminating `.')\n\
+         gnudb.gnudb.org http 80 /~cddb/cddb.cgi N000.00 W000.00 GnuDB\n\
+         gnudb.gnudb.org cddbp 8880 - N000.00 W000.00 GnuDB\n.\n",
+    );
+    let sites = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .http()
+        .unwrap()
+        .sites()
+        .unwrap();
+    assert_eq!(sites.len(), 2);
+    assert_eq!(sites[0], {
+        let mut site = Site::http("gnudb.gnudb.org", 80);
+        site.description = "GnuDB".to_owned();
+        site
+    });
+    assert_eq!(sites[1].protocol, "cddbp");
+    assert!(server.join().unwrap().contains("cmd=sites&"));
+}
+
+#[test]
+fn test_client_config_hello_local() {
+    init_logger();
+    let config = ClientConfig::new("joe", "my.host.com", "xmcd", "2.1").unwrap();
+    let (port, server) = serve_cddbp(&[(READY, &[])]);
+    let builder = ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .client_config(config);
+    let con = aw!(builder.connect()).unwrap();
+    drop(con);
+    let commands = server.join().unwrap();
+    assert_eq!(commands[0], "cddb hello joe my.host.com xmcd 2.1");
+
+    let (port, server) = serve_once("202 No match found\n");
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = builder
+        .http_port(port)
+        .http()
+        .unwrap()
+        .query(&discid)
+        .unwrap();
+    assert!(matches.is_empty());
+    let request = server.join().unwrap();
+    assert!(request.contains("hello=joe%20my.host.com%20xmcd%202.1&"));
+}
+
+#[test]
+fn test_client_config_rejected_before_connecting() {
+    let config = ClientConfig {
+        user: "joe doe".to_owned(),
+        ..Default::default()
+    };
+    let builder = ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(closed_port())
+        .http_port(closed_port())
+        .client_config(config);
+    let err = builder.http().unwrap_err();
+    assert!(matches!(err, GnuDbError::InvalidConfig(_)));
+    let err = aw!(builder.connect());
+    assert!(matches!(err, Err(GnuDbError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_cddbp_proto_fallback_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[
+            ("proto 6", "501 Illegal CDDB protocol level.\r\n"),
+            (
+                "proto",
+                "200 CDDB protocol level: current 1, supported 5\r\n",
+            ),
+            ("proto 5", "201 OK, CDDB protocol level now: 5\r\n"),
+        ],
+    )]);
+    let con = aw!(ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .connect())
+    .unwrap();
+    assert_eq!(con.proto_level(), 5);
+    drop(con);
+    let commands = server.join().unwrap();
+    assert_eq!(commands[1..], ["proto 6", "proto", "proto 5"]);
+}
+
+#[test]
+fn test_cddbp_read_timeout_local() {
+    init_logger();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // accept the connection, but never greet
+    let server = thread::spawn(move || listener.accept().unwrap());
+    let start = Instant::now();
+    let result = aw!(ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .read_timeout(Duration::from_millis(100))
+        .connect());
+    assert!(matches!(result, Err(GnuDbError::Timeout(_))));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http_client_builder_local() {
+    init_logger();
+    let (port, server) = serve_http(vec![
+        "501 Illegal CDDB protocol level.\n",
+        "200 CDDB protocol level: current 1, supported 5\n",
+        "200 rock 6909aa09 DIRE STRAITS / Dire Straits\n",
+        "202 No match found\n",
+    ]);
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .cgi_path("/cgi-bin/cddb.cgi")
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = client.query(&discid).unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(client.proto_level(), 5);
+    // the lowered level is kept for the next requests
+    assert!(client.query(&discid).unwrap().is_empty());
+
+    let requests = server.join().unwrap();
+    assert!(
+        requests
+            .iter()
+            .all(|r| r.starts_with("GET /cgi-bin/cddb.cgi?"))
+    );
+    assert!(requests[0].contains("&proto=6 "));
+    assert!(requests[1].contains("cmd=proto&"));
+    assert!(requests[2].contains("&proto=5 "));
+    assert!(requests[3].contains("&proto=5 "));
+}
+
+#[test]
+fn test_http_client_reuses_connection_local() {
+    init_logger();
+    let (port, server) = serve_keep_alive(vec![
+        "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\n",
+        "210 OK, category list follows (until terminating `.')\nrock\njazz\n.\n",
+        "200 Disc ID is 6909aa09\n",
+        "210 OK, help information follows (until terminating `.')\nsome help\n.\n",
+    ]);
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .read_timeout(Duration::from_secs(2))
+        .user_agent("batch-tagger/2.0")
+        .http()
+        .unwrap();
+    assert_eq!(client.ver().unwrap().version, "v1.5.2PL0");
+    assert_eq!(
+        client.lscat().unwrap(),
+        vec![Category::Rock, Category::Jazz]
+    );
+    assert_eq!(client.discid(&[150, 18_051], 2476).unwrap(), "6909aa09");
+    assert_eq!(client.help(None).unwrap(), "some help\n");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 4);
+    assert!(requests[0].contains("cmd=ver&"));
+    assert!(requests[1].contains("cmd=cddb%20lscat&"));
+    assert!(requests[2].contains("cmd=discid%202%20150%2018051%202476&"));
+    assert!(requests.iter().all(|r| {
+        r.to_lowercase()
+            .contains("user-agent: batch-tagger/2.0\r\n")
+    }));
+}
+
+#[test]
+fn test_http_client_invalid_proxy() {
+    let err = ClientBuilder::new().proxy("not a url").http().unwrap_err();
+    assert!(matches!(err, GnuDbError::InvalidConfig(_)));
+}
+
+#[test]
+fn test_async_http_client_local() {
+    init_logger();
+    let (port, server) = serve_keep_alive(vec![
+        "200 rock 6909aa09 DIRE STRAITS / Dire Straits\n",
+        "210 rock 6909aa09 CD database entry follows (until terminating `.')\n\
+         # xmcd\nDISCID=6909aa09\nDTITLE=Various / Hits\nTTITLE0=Dire Straits / Water of love\n.\n",
+        "210 OK, category list follows (until terminating `.')\nrock\njazz\n.\n",
+    ]);
+    let mut client: AsyncHttpClient = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .http_async()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = aw!(client.query(&discid)).unwrap();
+    assert_eq!(matches.len(), 1);
+    client.set_parse_options(ParseOptions {
+        split_track_artists: false,
+        ..Default::default()
+    });
+    let disc = aw!(client.read(&matches[0])).unwrap();
+    assert_eq!(disc.tracks[0].title, "Dire Straits / Water of love");
+    // clones share the connection
+    let mut clone = client.clone();
+    assert_eq!(
+        aw!(clone.lscat()).unwrap(),
+        vec![Category::Rock, Category::Jazz]
+    );
+    aw!(client.quit()).unwrap();
+    client.close();
+    assert!(!client.is_closed());
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("cmd=cddb%20query%20"));
+    assert!(requests[1].contains("cmd=cddb%20read%20rock%206909aa09&"));
+}
+
+/// transport independent lookup, as an application would write it
+async fn lookup_first(
+    client: &mut dyn CddbClient,
+    discid: &DiscId,
+) -> Result<Option<Disc>, GnuDbError> {
+    let matches = client.query(discid).await?;
+    match matches.first() {
+        Some(m) => client.read(m).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+#[test]
+fn test_cddb_client_transports_local() {
+    init_logger();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    const RECORD: &str = "210 rock 6909aa09 CD database entry follows (until terminating `.')\r\n\
+                  # xmcd\r\nDISCID=6909aa09\r\nDTITLE=DIRE STRAITS / Dire Straits\r\n\
+                  TTITLE0=Down to the waterline\r\n.\r\n";
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[
+            (
+                "cddb query",
+                "200 rock 6909aa09 DIRE STRAITS / Dire Straits\r\n",
+            ),
+            ("cddb read", RECORD),
+            ("cddb read", RECORD),
+        ],
+    )]);
+    let builder = ClientBuilder::new().server("127.0.0.1").cddbp_port(port);
+    let mut con = aw!(builder.open(Transport::Cddbp)).unwrap();
+    let disc = aw!(lookup_first(con.as_mut(), &discid)).unwrap().unwrap();
+    assert_eq!(disc.title, "Dire Straits");
+    let raw = aw!(con.read_raw(&sample_match())).unwrap();
+    assert!(raw.starts_with("# xmcd\nDISCID=6909aa09\nDTITLE=DIRE STRAITS / Dire Straits\n"));
+    assert!(matches!(
+        aw!(con.submit(&Submission {
+            category: Category::Rock,
+            discid: &discid,
+            disc: &disc,
+            email: "me@example.com",
+            mode: SubmitMode::Test,
+        })),
+        Err(GnuDbError::Unsupported(_))
+    ));
+    drop(con);
+    server.join().unwrap();
+
+    // the same lookup against an offline database, filled through the trait
+    let root = temp_root("transports");
+    let mut db = LocalDb::new(&root);
+    assert!(aw!(lookup_first(&mut db, &discid)).is_err());
+    let response = aw!(db.submit(&Submission {
+        category: Category::Rock,
+        discid: &discid,
+        disc: &sample_disc(),
+        email: "me@example.com",
+        mode: SubmitMode::Submit,
+    }))
+    .unwrap();
+    assert_eq!(response.code, 200);
+    let disc = aw!(lookup_first(&mut db, &discid)).unwrap().unwrap();
+    assert_eq!(disc.tracks.len(), 2);
+    let stored = Match {
+        discid: discid.freedb_id(),
+        ..sample_match()
+    };
+    assert_eq!(aw!(db.read_raw(&stored)).unwrap(), disc.to_xmcd());
+    assert_eq!(aw!(db.lscat()).unwrap(), vec![Category::Rock]);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_blocking_connection_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[
+            (
+                "cddb query",
+                "200 rock 6909aa09 DIRE STRAITS / Dire Straits\r\n",
+            ),
+            (
+                "cddb read",
+                "210 rock 6909aa09 CD database entry follows (until terminating `.')\r\n\
+             # xmcd\r\nDISCID=6909aa09\r\nDTITLE=DIRE STRAITS / Dire Straits\r\n\
+             TTITLE0=Down to the waterline\r\n.\r\n",
+            ),
+            ("quit", "230 localhost Closing connection.  Goodbye.\r\n"),
+        ],
+    )]);
+    let mut con = crate::blocking::Connection::from_host_port("127.0.0.1", port).unwrap();
+    assert_eq!(con.proto_level(), 6);
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = con.query(&discid).unwrap();
+    assert_eq!(matches.len(), 1);
+    let disc = con.read(&matches[0]).unwrap();
+    assert_eq!(disc.tracks[0].title, "Down to the waterline");
+    con.quit().unwrap();
+
+    let commands = server.join().unwrap();
+    assert!(commands[2].starts_with("cddb query "));
+    assert_eq!(commands[3..], ["cddb read rock 6909aa09", "quit"]);
+}
+
+#[test]
+fn test_http_base_url_local() {
+    init_logger();
+    let (port, server) = serve_once("202 No match found\n");
+    let client = ClientBuilder::new()
+        .server("unused.example.com")
+        .base_url(&format!("http://127.0.0.1:{port}/mirror/"))
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert!(client.query(&discid).unwrap().is_empty());
+    assert!(
+        server
+            .join()
+            .unwrap()
+            .starts_with("GET /mirror/~cddb/cddb.cgi?")
+    );
+
+    for url in [
+        "ftp://127.0.0.1",
+        "https://",
+        "127.0.0.1:80",
+        "http:///cddb",
+    ] {
+        let err = ClientBuilder::new().base_url(url).http().unwrap_err();
+        assert!(
+            matches!(err, GnuDbError::InvalidConfig(_)),
+            "{url}: {err:?}"
+        );
+    }
+}
+
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+#[test]
+fn test_https_private_root_local() {
+    init_logger();
+    let (port, server) = serve_https_once("200 rock 6909aa09 DIRE STRAITS / Dire Straits\n");
+    let client = ClientBuilder::new()
+        .base_url(&format!("https://localhost:{port}"))
+        .root_certificates_pem(include_bytes!("../testdata/ca.pem"))
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = client.query(&discid).unwrap();
+    assert_eq!(matches.len(), 1);
+    let request = server.join().unwrap().unwrap();
+    assert!(request.starts_with("GET /~cddb/cddb.cgi?cmd=cddb%20query%20"));
+
+    // the private CA is not trusted by default
+    let (port, server) = serve_https_once("202 No match found\n");
+    let client = ClientBuilder::new()
+        .base_url(&format!("https://localhost:{port}"))
+        .http()
+        .unwrap();
+    match client.query(&discid) {
+        Err(e @ GnuDbError::ProtocolError(_)) => assert!(!e.is_retryable()),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    assert!(server.join().unwrap().is_none());
+}
+
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+#[test]
+fn test_https_invalid_root_certificate() {
+    let err = ClientBuilder::new()
+        .root_certificates_pem(b"not a certificate")
+        .http()
+        .unwrap_err();
+    assert!(matches!(err, GnuDbError::InvalidConfig(_)));
+}
+
+#[test]
+fn test_http_proxy_auth_local() {
+    init_logger();
+    let (proxy_port, proxy) = serve_proxy("202 No match found\n");
+    // the target is never resolved by the client, only by the proxy
+    let client = ClientBuilder::new()
+        .server("cddb.invalid")
+        .proxy(&format!("http://127.0.0.1:{proxy_port}"))
+        .proxy_auth("joe", "secret")
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert!(client.query(&discid).unwrap().is_empty());
+    let (head, request) = proxy.join().unwrap();
+    assert!(head.starts_with("CONNECT cddb.invalid:80 HTTP/1.1\r\n"));
+    // base64 of joe:secret
+    assert!(
+        head.lines()
+            .any(|line| line.eq_ignore_ascii_case("proxy-authorization: basic am9lOnNlY3JldA==")),
+        "{head}"
+    );
+    assert!(request.starts_with("GET /~cddb/cddb.cgi?cmd=cddb%20query%20"));
+}
+
+#[test]
+fn test_http_no_proxy_local() {
+    init_logger();
+    let (port, server) = serve_once("202 No match found\n");
+    // nobody listens on the proxy port, so the request must go around it
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .proxy(&format!("http://127.0.0.1:{}", closed_port()))
+        .no_proxy("127.0.0.1")
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert!(client.query(&discid).unwrap().is_empty());
+    assert!(server.join().unwrap().starts_with("GET /~cddb/cddb.cgi?"));
+}
+
+fn fast_retry() -> RetryPolicy {
+    RetryPolicy::new()
+        .initial_backoff(Duration::from_millis(10))
+        .jitter(false)
+}
+
+#[test]
+fn test_cddbp_retry_busy_server_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[
+        (
+            "433 No connections allowed: 1 users allowed, 1 currently active\r\n",
+            &[],
+        ),
+        (
+            READY,
+            &[("quit", "230 localhost Closing connection.  Goodbye.\r\n")],
+        ),
+    ]);
+    let builder = ClientBuilder::new().server("127.0.0.1").cddbp_port(port);
+    let mut con = aw!(builder.clone().retry(fast_retry()).connect()).unwrap();
+    aw!(con.quit()).unwrap();
+    assert_eq!(
+        server.join().unwrap().last().map(String::as_str),
+        Some("quit")
+    );
+
+    // without a retry policy the busy server is reported
+    let (busy, _) = serve_cddbp(&[("434 No connections allowed: system load too high\r\n", &[])]);
+    match aw!(builder.cddbp_port(busy).connect()) {
+        Err(GnuDbError::ServerBusy { code, .. }) => assert_eq!(code, 434),
+        Err(other) => panic!("unexpected error type: {other:?}"),
+        Ok(_) => panic!("connected to a busy server"),
+    }
+}
+
+#[test]
+fn test_cddbp_retry_reconnects_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[
+        // the server drops the connection instead of answering the query
+        (
+            READY,
+            &[(
+                "ver",
+                "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\r\n",
+            )],
+        ),
+        (
+            READY,
+            &[(
+                "cddb query",
+                "200 rock 6909aa09 DIRE STRAITS / Dire Straits\r\n",
+            )],
+        ),
+    ]);
+    let mut con = aw!(ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .retry(fast_retry())
+        .connect())
+    .unwrap();
+    aw!(con.ver()).unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = aw!(con.query(&discid)).unwrap();
+    assert_eq!(matches[0].category, Category::Rock);
+    con.close();
+    let commands = server.join().unwrap();
+    let queries = commands
+        .iter()
+        .filter(|cmd| cmd.starts_with("cddb query"))
+        .count();
+    assert_eq!(queries, 2);
+    assert_eq!(
+        commands
+            .iter()
+            .filter(|cmd| cmd.starts_with("cddb hello"))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_http_retry_local() {
+    init_logger();
+    let (port, server) = serve_http(vec![
+        "434 No connections allowed: system load too high\n",
+        "200 rock 6909aa09 DIRE STRAITS / Dire Straits\n",
+    ]);
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .retry(fast_retry())
+        .http()
+        .unwrap();
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    let matches = client.query(&discid).unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(server.join().unwrap().len(), 2);
+
+    // errors that do not go away are not retried
+    let (port, server) = serve_once("500 Command syntax error\n");
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .retry(fast_retry())
+        .http()
+        .unwrap();
+    assert!(matches!(
+        client.query(&discid),
+        Err(GnuDbError::ServerError { code: 500, .. })
+    ));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http_rate_limit_local() {
+    init_logger();
+    let (port, server) = serve_http(vec![
+        "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\n";
+        3
+    ]);
+    let limiter = RateLimiter::new(1, Duration::from_millis(200));
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .rate_limiter(limiter)
+        .http()
+        .unwrap();
+    let start = Instant::now();
+    for _ in 0..3 {
+        client.ver().unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(server.join().unwrap().len(), 3);
+
+    // the requests of a protocol level fallback are counted one by one
+    let (port, server) = serve_http(vec![
+        "501 Illegal CDDB protocol level.\n",
+        "200 CDDB protocol level: current 1, supported 5\n",
+        "200 cddbd v1.5.2PL0 Copyright (c) Steve Scherf et al.\n",
+    ]);
+    let client = ClientBuilder::new()
+        .server("127.0.0.1")
+        .http_port(port)
+        .rate_limiter(RateLimiter::new(1, Duration::from_millis(200)))
+        .http()
+        .unwrap();
+    let start = Instant::now();
+    client.ver().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(server.join().unwrap().len(), 3);
+}
+
+#[test]
+fn test_cddbp_rate_limit_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(
+        READY,
+        &[("quit", "230 localhost Closing connection.  Goodbye.\r\n")],
+    )]);
+    let start = Instant::now();
+    // hello, proto and quit are requests like any other command
+    let mut con = aw!(ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .rate_limiter(RateLimiter::new(1, Duration::from_millis(200)))
+        .connect())
+    .unwrap();
+    aw!(con.quit()).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(server.join().unwrap().len(), 3);
+}
+
+#[test]
+fn test_cddbp_connections_per_host_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(READY, &[]), (READY, &[])]);
+    let builder = ClientBuilder::new()
+        .server("127.0.0.1")
+        .cddbp_port(port)
+        .rate_limiter(RateLimiter::new(100, Duration::from_secs(1)).max_connections_per_host(1));
+    let first = aw!(builder.connect()).unwrap();
+    let start = Instant::now();
+    let closer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        drop(first);
+    });
+    // the second connection waits until the first one is closed
+    let mut second = aw!(builder.connect()).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    second.close();
+    closer.join().unwrap();
+    server.join().unwrap();
+}
+
+const POOL_QUERY: (&str, &str) = (
+    "cddb query",
+    "200 rock 6909aa09 DIRE STRAITS / Dire Straits\r\n",
+);
+
+fn pool_for(port: u16) -> ConnectionPool {
+    ConnectionPool::new(ClientBuilder::new().server("127.0.0.1").cddbp_port(port))
+}
+
+fn count(commands: &[String], prefix: &str) -> usize {
+    commands
+        .iter()
+        .filter(|cmd| cmd.starts_with(prefix))
+        .count()
+}
+
+#[test]
+fn test_pool_reuses_connections_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(READY, &[POOL_QUERY; 3])]);
+    let pool = pool_for(port).max_size(1);
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert_eq!(aw!(pool.query(&discid)).unwrap().len(), 1);
+    assert_eq!(aw!(pool.query(&discid)).unwrap().len(), 1);
+    assert_eq!(pool.idle_connections(), 1);
+
+    // the only connection is in use, the next one waits until it is returned
+    let first = aw!(pool.get()).unwrap();
+    let start = Instant::now();
+    let returner = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        drop(first);
+    });
+    // a waiter that gives up does not keep the returned connection from the next one
+    let gave_up = aw!(pool.get().or(async {
+        crate::runtime::sleep(Duration::from_millis(10)).await;
+        Err(GnuDbError::Timeout("gave up".to_owned()))
+    }));
+    assert!(gave_up.is_err());
+    let mut second = aw!(pool.get()).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(aw!(second.query(&discid)).unwrap().len(), 1);
+    drop(second);
+    returner.join().unwrap();
+    drop(pool);
+
+    let commands = server.join().unwrap();
+    assert_eq!(count(&commands, "cddb hello"), 1);
+    assert_eq!(count(&commands, "cddb query"), 3);
+}
+
+#[test]
+fn test_pool_evicts_closed_connections_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[
+        (
+            READY,
+            &[("cddb query", "530 Server error, closing connection.\r\n")],
+        ),
+        // the second connection does not answer the health check
+        (READY, &[POOL_QUERY]),
+        (READY, &[POOL_QUERY]),
+    ]);
+    let pool = pool_for(port).health_check_after(Duration::ZERO);
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    assert!(matches!(
+        aw!(pool.query(&discid)),
+        Err(GnuDbError::ServerError { code: 530, .. })
+    ));
+    assert_eq!(pool.idle_connections(), 0);
+    assert_eq!(aw!(pool.query(&discid)).unwrap().len(), 1);
+    assert_eq!(pool.idle_connections(), 1);
+    assert_eq!(aw!(pool.query(&discid)).unwrap().len(), 1);
+    drop(pool);
+
+    let commands = server.join().unwrap();
+    assert_eq!(count(&commands, "cddb hello"), 3);
+    assert_eq!(count(&commands, "ver"), 1);
+}
+
+#[test]
+fn test_pool_idle_timeout_local() {
+    init_logger();
+    let (port, server) = serve_cddbp(&[(READY, &[POOL_QUERY]), (READY, &[POOL_QUERY])]);
+    let mut pool = pool_for(port).idle_timeout(Duration::ZERO);
+    let discid = DiscId::put(1, &[42_248, 150, 18_051]).unwrap();
+    // through the transport independent trait
+    assert_eq!(aw!(CddbClient::query(&mut pool, &discid)).unwrap().len(), 1);
+    assert_eq!(aw!(CddbClient::query(&mut pool, &discid)).unwrap().len(), 1);
+    drop(pool);
+    assert_eq!(count(&server.join().unwrap(), "cddb hello"), 2);
+}
@@ -0,0 +1,204 @@
+use std::{fmt, future::Future, pin::Pin, str::FromStr};
+
+use discid::DiscId;
+
+use crate::error::GnuDbError;
+use crate::{
+    AsyncHttpClient, Category, ClientBuilder, Connection, Disc, Match, Site, Submission,
+    SubmitResponse, cddbp, parser,
+};
+
+/// Future returned by the methods of `CddbClient`
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Transport independent access to a CDDB database
+///
+/// Implemented by the CDDBP `Connection` and `ConnectionPool`, the `AsyncHttpClient` and the offline `LocalDb`,
+/// so code written against it can switch transport at runtime, or be tested against a fake.
+/// `CachedClient` puts a disk cache in front of any of them.
+/// Only query and read are required, the other commands report `GnuDbError::Unsupported`
+/// unless the transport implements them.
+/// ```no_run
+/// use discid::DiscId;
+/// use gnudb::{CddbClient, ClientBuilder, Transport};
+///
+/// #[cfg(feature = "runtime-smol")]
+/// smol::block_on(async {
+///     let transport: Transport = "http".parse().unwrap();
+///     let mut client = ClientBuilder::new().open(transport).await.unwrap();
+///     let discid = DiscId::read(Some(DiscId::default_device().as_str())).unwrap();
+///     let matches = client.query(&discid).await.unwrap();
+///     let _disc = client.read(&matches[0]).await.unwrap();
+/// });
+/// ```
+pub trait CddbClient {
+    /// find the matches for a given discid, empty when the disc is unknown
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>>;
+
+    /// read all data of a given disc
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>>;
+
+    /// read the xmcd record of a given disc as the database holds it, without the status line
+    /// defaults to the record written from `read`, which drops comments the parser does not know
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        let read = self.read(single_match);
+        Box::pin(async move { read.await.map(|disc| disc.to_xmcd()) })
+    }
+
+    /// list the servers of the database
+    fn sites(&mut self) -> BoxFuture<'_, Result<Vec<Site>, GnuDbError>> {
+        unsupported("sites")
+    }
+
+    /// list the categories of the database
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        unsupported("lscat")
+    }
+
+    /// submit a disc entry
+    fn submit<'a>(
+        &'a mut self,
+        _submission: &'a Submission<'a>,
+    ) -> BoxFuture<'a, Result<SubmitResponse, GnuDbError>> {
+        unsupported("submit")
+    }
+}
+
+fn unsupported<'a, T: 'a>(command: &str) -> BoxFuture<'a, Result<T, GnuDbError>> {
+    let err = GnuDbError::Unsupported(command.to_owned());
+    Box::pin(async move { Err(err) })
+}
+
+// the DiscId is not Send, so the query commands and submitted records are built
+// before the futures are created
+
+impl CddbClient for Connection {
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+        let cmd = parser::create_query_cmd(discid);
+        Box::pin(async move { cddbp::cddb_query(self, cmd?).await })
+    }
+
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+        Box::pin(Connection::read(self, single_match))
+    }
+
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        Box::pin(cddbp::cddb_read_raw(self, single_match))
+    }
+
+    fn sites(&mut self) -> BoxFuture<'_, Result<Vec<Site>, GnuDbError>> {
+        Box::pin(Connection::sites(self))
+    }
+
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        Box::pin(Connection::lscat(self))
+    }
+}
+
+impl CddbClient for AsyncHttpClient {
+    fn query(&mut self, discid: &DiscId) -> BoxFuture<'_, Result<Vec<Match>, GnuDbError>> {
+        let cmd = parser::create_query_cmd(discid);
+        Box::pin(async move { self.query_cmd(cmd?).await })
+    }
+
+    fn read<'a>(&'a mut self, single_match: &'a Match) -> BoxFuture<'a, Result<Disc, GnuDbError>> {
+        Box::pin(AsyncHttpClient::read(self, single_match))
+    }
+
+    fn read_raw<'a>(
+        &'a mut self,
+        single_match: &'a Match,
+    ) -> BoxFuture<'a, Result<String, GnuDbError>> {
+        Box::pin(AsyncHttpClient::read_raw(self, single_match))
+    }
+
+    fn sites(&mut self) -> BoxFuture<'_, Result<Vec<Site>, GnuDbError>> {
+        Box::pin(AsyncHttpClient::sites(self))
+    }
+
+    fn lscat(&mut self) -> BoxFuture<'_, Result<Vec<Category>, GnuDbError>> {
+        Box::pin(AsyncHttpClient::lscat(self))
+    }
+
+    fn submit<'a>(
+        &'a mut self,
+        submission: &'a Submission<'a>,
+    ) -> BoxFuture<'a, Result<SubmitResponse, GnuDbError>> {
+        let record = self.submit_record(submission);
+        Box::pin(async move { self.post_record(record?).await })
+    }
+}
+
+/// Network transport of a `CddbClient`, see `ClientBuilder::open`
+/// Parses from the protocol names used in the site list: "cddbp" or "http"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Cddbp,
+    Http,
+}
+
+impl Transport {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transport::Cddbp => "cddbp",
+            Transport::Http => "http",
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Transport {
+    type Err = GnuDbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cddbp" => Ok(Transport::Cddbp),
+            "http" => Ok(Transport::Http),
+            _ => Err(GnuDbError::InvalidConfig(format!(
+                "unknown transport '{s}'"
+            ))),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// open a client for the server over the given transport
+    pub async fn open(
+        &self,
+        transport: Transport,
+    ) -> Result<Box<dyn CddbClient + Send>, GnuDbError> {
+        Ok(match transport {
+            Transport::Cddbp => Box::new(self.connect().await?),
+            Transport::Http => Box::new(self.http_async()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_transport() -> Result<(), GnuDbError> {
+        assert_eq!("cddbp".parse::<Transport>()?, Transport::Cddbp);
+        assert_eq!(" HTTP ".parse::<Transport>()?, Transport::Http);
+        assert_eq!(Transport::Http.to_string(), "http");
+        assert!(matches!(
+            "ftp".parse::<Transport>(),
+            Err(GnuDbError::InvalidConfig(_))
+        ));
+        Ok(())
+    }
+}
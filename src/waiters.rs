@@ -0,0 +1,160 @@
+//! Tasks waiting for a shared resource, used by `ConnectionPool` and `RateLimiter`
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    task::{Poll, Waker},
+};
+
+use futures_lite::future;
+
+/// The wakers of the tasks waiting for a resource, one slot per waiting future,
+/// woken one at a time in the order they started waiting
+#[derive(Debug, Default)]
+pub(crate) struct Waiters {
+    next_id: u64,
+    wakers: BTreeMap<u64, Waker>,
+}
+
+impl Waiters {
+    /// store the waker of a waiter, replacing the one it registered before
+    fn register(&mut self, id: &mut Option<u64>, waker: &Waker) {
+        let id = *id.get_or_insert_with(|| {
+            self.next_id += 1;
+            self.next_id
+        });
+        match self.wakers.get_mut(&id) {
+            Some(registered) if registered.will_wake(waker) => {}
+            Some(registered) => registered.clone_from(waker),
+            None => {
+                self.wakers.insert(id, waker.clone());
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.wakers.is_empty()
+    }
+
+    /// wake the task that waits longest, call once for every resource that is freed
+    pub(crate) fn wake_one(&mut self) {
+        if let Some((_, waker)) = self.wakers.pop_first() {
+            waker.wake();
+        }
+    }
+}
+
+/// wait until `ready` takes the resource out of the locked state
+/// the slot of the waiter is removed when the future completes or is dropped, a wakeup it got
+/// without taking the resource is passed on to the next waiter
+pub(crate) async fn wait_for<S, T>(
+    state: &Mutex<S>,
+    waiters: impl Fn(&mut S) -> &mut Waiters,
+    mut ready: impl FnMut(&mut S) -> Option<T>,
+) -> T {
+    let mut waiter = Waiter {
+        state,
+        waiters,
+        id: None,
+    };
+    future::poll_fn(|cx| {
+        let mut state = lock(waiter.state);
+        if let Some(taken) = ready(&mut state) {
+            if let Some(id) = waiter.id.take() {
+                (waiter.waiters)(&mut state).wakers.remove(&id);
+            }
+            return Poll::Ready(taken);
+        }
+        (waiter.waiters)(&mut state).register(&mut waiter.id, cx.waker());
+        Poll::Pending
+    })
+    .await
+}
+
+fn lock<S>(state: &Mutex<S>) -> MutexGuard<'_, S> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// the slot of a waiting future
+struct Waiter<'a, S, W: Fn(&mut S) -> &mut Waiters> {
+    state: &'a Mutex<S>,
+    waiters: W,
+    /// None before the first registration and once the resource is taken
+    id: Option<u64>,
+}
+
+impl<S, W: Fn(&mut S) -> &mut Waiters> Drop for Waiter<'_, S, W> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = lock(self.state);
+        let waiters = (self.waiters)(&mut state);
+        // gone from the list: it was woken for a resource it will not take anymore
+        if waiters.wakers.remove(&id).is_none() {
+            waiters.wake_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        future::Future,
+        sync::Arc,
+        task::{Context, Wake},
+    };
+
+    #[derive(Default)]
+    struct State {
+        free: usize,
+        waiters: Waiters,
+    }
+
+    fn waiter(state: &Mutex<State>) -> impl Future<Output = ()> + '_ {
+        wait_for(
+            state,
+            |state: &mut State| &mut state.waiters,
+            |state| state.free.checked_sub(1).map(|free| state.free = free),
+        )
+    }
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn test_one_slot_per_waiter() {
+        let state = Mutex::new(State::default());
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut first = Box::pin(waiter(&state));
+        let mut second = Box::pin(waiter(&state));
+        let mut third = Box::pin(waiter(&state));
+        for _ in 0..3 {
+            assert!(first.as_mut().poll(&mut cx).is_pending());
+        }
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(third.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(lock(&state).waiters.wakers.len(), 3);
+
+        // a cancelled waiter leaves the list
+        drop(third);
+        assert_eq!(lock(&state).waiters.wakers.len(), 2);
+
+        // the first waiter is woken, but dropped before it takes the resource
+        let mut locked = lock(&state);
+        locked.free = 1;
+        locked.waiters.wake_one();
+        drop(locked);
+        drop(first);
+        // so the wakeup went to the second one
+        assert!(lock(&state).waiters.wakers.is_empty());
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(lock(&state).free, 0);
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt::Write;
+
+use discid::DiscId;
+
+use crate::{ClientConfig, Disc};
+
+/// maximum length of a line in an xmcd record, including the terminating newline
+const MAX_LINE_LEN: usize = 256;
+
+/// serialize a disc into an xmcd record, keeping its submitter and processor
+pub(crate) fn write_xmcd(disc: &Disc) -> String {
+    let submitted_via = disc
+        .submitted_via
+        .clone()
+        .unwrap_or_else(|| ClientConfig::default().submitted_via());
+    write_record(disc, &submitted_via, disc.processed_by.as_deref())
+}
+
+/// serialize a disc for submission, with the TOC of the given discid:
+/// the server adds "# Processed by:" itself, and "# Submitted via:" must name this client
+pub(crate) fn write_submission(disc: &Disc, discid: &DiscId, config: &ClientConfig) -> String {
+    let mut disc = disc.clone();
+    disc.set_toc(discid);
+    write_record(&disc, &config.submitted_via(), None)
+}
+
+fn write_record(disc: &Disc, submitted_via: &str, processed_by: Option<&str>) -> String {
+    let mut out = String::new();
+    out.push_str("# xmcd\n#\n# Track frame offsets:\n");
+    for track in &disc.tracks {
+        let _ = writeln!(out, "#\t{}", track.offset);
+    }
+    out.push_str("#\n");
+    let _ = writeln!(out, "# Disc length: {} seconds", disc.length);
+    out.push_str("#\n");
+    let _ = writeln!(out, "# Revision: {}", disc.revision);
+    if let Some(processed_by) = processed_by {
+        let _ = writeln!(out, "# Processed by: {processed_by}");
+    }
+    let _ = writeln!(out, "# Submitted via: {submitted_via}");
+    out.push_str("#\n");
+
+    write_field(&mut out, "DISCID", &disc.discids.join(","));
+    if disc.artist.is_empty() {
+        write_field(&mut out, "DTITLE", &disc.title);
+    } else {
+        write_field(
+            &mut out,
+            "DTITLE",
+            &format!("{} / {}", disc.artist, disc.title),
+        );
+    }
+    let year = disc.year.map(|year| year.to_string()).unwrap_or_default();
+    write_field(&mut out, "DYEAR", &year);
+    write_field(&mut out, "DGENRE", disc.genre.as_deref().unwrap_or(""));
+    for (idx, track) in disc.tracks.iter().enumerate() {
+        let key = format!("TTITLE{idx}");
+        if track.artist.is_empty() || track.artist == disc.artist {
+            write_field(&mut out, &key, &track.title);
+        } else {
+            write_field(
+                &mut out,
+                &key,
+                &format!("{} / {}", track.artist, track.title),
+            );
+        }
+    }
+    write_field(&mut out, "EXTD", &disc.extended_data);
+    for (idx, track) in disc.tracks.iter().enumerate() {
+        write_field(&mut out, &format!("EXTT{idx}"), &track.extended);
+    }
+    let play_order: Vec<String> = disc.play_order.iter().map(u32::to_string).collect();
+    write_field(&mut out, "PLAYORDER", &play_order.join(","));
+    out
+}
+
+/// write a keyword=value line, escaping the value and splitting it over
+/// multiple lines with the same keyword when it does not fit in `MAX_LINE_LEN`
+fn write_field(out: &mut String, key: &str, value: &str) {
+    let escaped = escape(value);
+    // room for the value on each line: "KEY=" and the newline take up the rest
+    let room = MAX_LINE_LEN.saturating_sub(key.len() + 2).max(4);
+    let mut rest = escaped.as_str();
+    loop {
+        let split = split_point(rest, room);
+        let (chunk, tail) = rest.split_at(split);
+        let _ = writeln!(out, "{key}={chunk}");
+        if tail.is_empty() {
+            break;
+        }
+        rest = tail;
+    }
+}
+
+/// find the largest byte index <= `room` at which an escaped value can be split,
+/// never splitting a UTF-8 character or a backslash escape sequence
+fn split_point(value: &str, room: usize) -> usize {
+    if value.len() <= room {
+        return value.len();
+    }
+    let mut end = 0;
+    let mut chars = value.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        let mut next = idx + c.len_utf8();
+        if c == '\\'
+            && let Some((_, escaped)) = chars.next()
+        {
+            next += escaped.len_utf8();
+        }
+        if next > room {
+            break;
+        }
+        end = next;
+    }
+    end
+}
+
+/// escape a value as required by the xmcd format
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_read_response_with;
+    use crate::{ParseOptions, Track, error::GnuDbError};
+
+    fn sample_disc() -> Disc {
+        Disc {
+            title: "Album".to_owned(),
+            artist: "Artist".to_owned(),
+            year: Some(1978),
+            genre: Some("Rock".to_owned()),
+            length: 2476,
+            revision: 3,
+            discids: vec!["6909aa09".to_owned()],
+            tracks: [150, 18_051, 42_248]
+                .iter()
+                .zip(0..)
+                .map(|(offset, i)| Track {
+                    number: i + 1,
+                    title: format!("Song {i}"),
+                    offset: *offset,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_write_xmcd() {
+        let xmcd = write_xmcd(&sample_disc());
+        assert!(xmcd.starts_with("# xmcd\n#\n# Track frame offsets:\n"));
+        assert!(xmcd.contains("#\t150\n#\t18051\n#\t42248\n#\n"));
+        assert!(xmcd.contains("# Disc length: 2476 seconds\n"));
+        assert!(xmcd.contains(&format!(
+            "# Revision: 3\n# Submitted via: gnudb {}\n#\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(xmcd.contains("DISCID=6909aa09\nDTITLE=Artist / Album\n"));
+        assert!(xmcd.contains("DYEAR=1978\nDGENRE=Rock\n"));
+        assert!(xmcd.contains("TTITLE2=Song 2\nEXTD=\nEXTT0=\n"));
+        assert!(xmcd.ends_with("EXTT2=\nPLAYORDER=\n"));
+    }
+
+    #[test]
+    fn test_write_keeps_submitter_and_processor() {
+        let mut disc = sample_disc();
+        disc.processed_by = Some("cddbd v1.4PL0".to_owned());
+        disc.submitted_via = Some("EasyCDDAExtractor 5.1.0".to_owned());
+        let xmcd = write_xmcd(&disc);
+        assert!(xmcd.contains(
+            "# Revision: 3\n# Processed by: cddbd v1.4PL0\n# Submitted via: EasyCDDAExtractor 5.1.0\n"
+        ));
+        let discid = DiscId::put(1, &[185_700, 150, 18_051, 42_248]).unwrap();
+        let config = ClientConfig::new("joe", "my.host.com", "xmcd", "2.1").unwrap();
+        let submission = write_submission(&disc, &discid, &config);
+        assert!(submission.contains("# Revision: 3\n# Submitted via: xmcd 2.1\n"));
+        assert!(!submission.contains("Processed by"));
+    }
+
+    #[test]
+    fn test_write_extended_data_and_play_order() {
+        let mut disc = sample_disc();
+        disc.extended_data = "Liner notes\n YEAR: 1978".to_owned();
+        disc.tracks[1].extended = "Live".to_owned();
+        disc.play_order = vec![2, 0, 1];
+        let xmcd = write_xmcd(&disc);
+        assert!(xmcd.contains("EXTD=Liner notes\\n YEAR: 1978\nEXTT0=\nEXTT1=Live\nEXTT2=\n"));
+        assert!(xmcd.ends_with("PLAYORDER=2,0,1\n"));
+    }
+
+    #[test]
+    fn test_write_multiple_discids() {
+        let mut disc = sample_disc();
+        disc.discids.push("6909aa0a".to_owned());
+        let xmcd = write_xmcd(&disc);
+        assert!(xmcd.contains("DISCID=6909aa09,6909aa0a\n"));
+    }
+
+    #[test]
+    fn test_write_empty_year_and_genre() {
+        let mut disc = sample_disc();
+        disc.year = None;
+        disc.genre = None;
+        disc.artist = String::new();
+        let xmcd = write_xmcd(&disc);
+        assert!(xmcd.contains("DTITLE=Album\nDYEAR=\nDGENRE=\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\nb\tc\\d"), "a\\nb\\tc\\\\d");
+        assert_eq!(escape("trailing\\"), "trailing\\\\");
+    }
+
+    #[test]
+    fn test_write_escapes_values() {
+        let mut disc = sample_disc();
+        disc.tracks[0].title = "Part 1\nPart 2\t(live) C:\\".to_owned();
+        let xmcd = write_xmcd(&disc);
+        assert!(xmcd.contains("TTITLE0=Part 1\\nPart 2\\t(live) C:\\\\\n"));
+    }
+
+    #[test]
+    fn test_write_splits_long_lines() {
+        let mut disc = sample_disc();
+        let long_title = "Symphonie No. 9 in d-Moll, op. 125 ".repeat(20);
+        disc.tracks[1].title.clone_from(&long_title);
+        let xmcd = write_xmcd(&disc);
+        let lines: Vec<&str> = xmcd
+            .lines()
+            .filter(|line| line.starts_with("TTITLE1="))
+            .collect();
+        assert!(lines.len() > 1);
+        assert!(xmcd.lines().all(|line| line.len() < MAX_LINE_LEN));
+        let joined: String = lines
+            .iter()
+            .map(|line| line.trim_start_matches("TTITLE1="))
+            .collect();
+        assert_eq!(joined, long_title);
+    }
+
+    #[test]
+    fn test_split_keeps_escapes_and_chars_intact() {
+        let value = "\\\\é\\n";
+        // an escape sequence is never split from its backslash
+        assert_eq!(split_point(value, 1), 0);
+        assert_eq!(split_point(value, 2), 2);
+        // a multi-byte char is never split
+        assert_eq!(split_point(value, 3), 2);
+        assert_eq!(split_point(value, 4), 4);
+        assert_eq!(split_point(value, 6), 6);
+    }
+
+    /// writing a parsed record and parsing it back must give the same disc
+    fn assert_round_trip(fixture: &str) -> Result<(), GnuDbError> {
+        let disc = parse_read_response_with(fixture, &ParseOptions::default())?;
+        let written = write_xmcd(&disc);
+        let reparsed = parse_read_response_with(&written, &ParseOptions::default())?;
+        assert_eq!(reparsed, disc);
+        // and writing it again must be stable
+        assert_eq!(write_xmcd(&reparsed), written);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip_rammstein() -> Result<(), GnuDbError> {
+        assert_round_trip(crate::parser::tests::RAMMSTEIN)
+    }
+
+    #[test]
+    fn test_round_trip_dire_straits() -> Result<(), GnuDbError> {
+        assert_round_trip(crate::parser::tests::DIRE_STRAITS)
+    }
+
+    #[test]
+    fn test_round_trip_slash_in_artist() -> Result<(), GnuDbError> {
+        let fixture = crate::parser::tests::DIRE_STRAITS.replace(
+            "DTITLE=DIRE STRAITS / Dire Straits",
+            "DTITLE=AC/DC / Back in Black",
+        );
+        let disc = parse_read_response_with(&fixture, &ParseOptions::default())?;
+        assert_eq!(disc.artist, "AC/DC");
+        assert_eq!(disc.title, "Back in Black");
+        assert_round_trip(&fixture)
+    }
+}